@@ -1,4 +1,6 @@
 use std::fmt::{Error, Formatter};
+use std::sync::Arc;
+use crate::texture::{Texture, TextureCoords};
 
 #[derive(Copy, Clone, Debug, PartialEq)]
 pub struct Vector3 {
@@ -12,12 +14,24 @@ impl Vector3 {
         Vector3{ x, y, z }
     }
 
+    pub fn get_x(&self) -> f64 {
+        self.x
+    }
+
+    pub fn get_y(&self) -> f64 {
+        self.y
+    }
+
+    pub fn get_z(&self) -> f64 {
+        self.z
+    }
+
     pub fn mag(&self) -> f64 {
         (self.x * self.x + self.y * self.y + self.z * self.z).sqrt() //TODO: implement the square root in a more efficient way
     }
 
     pub fn norm(&self) -> Vector3 {
-        self.clone() / self.mag()
+        *self / self.mag()
     }
 
     pub fn dot(&self, vec: Vector3) -> f64 {
@@ -31,6 +45,23 @@ impl Vector3 {
     pub fn dist_between(vec1: &Vector3, vec2: &Vector3) -> f64 {
         (*vec1 - *vec2).mag()
     }
+
+    /// Rotates the vector `angle` radians counterclockwise around `axis`.
+    pub fn rotate_around(&self, axis: &Vector3, angle: f64) -> Vector3 {
+        let axis = axis.norm();
+        let (sin, cos) = angle.sin_cos();
+        *self * cos + axis.cross(*self) * sin + axis * axis.dot(*self) * (1.0 - cos)
+    }
+
+    /// Some unit vector perpendicular to this one.
+    pub fn perpendicular(&self) -> Vector3 {
+        let helper: Vector3 = if self.x.abs() < 0.9 * self.mag() {
+            (1.0, 0.0, 0.0).into()
+        } else {
+            (0.0, 1.0, 0.0).into()
+        };
+        helper.cross(*self).norm()
+    }
 }
 
 impl std::ops::Add for Vector3 {
@@ -220,9 +251,9 @@ pub struct Color {
 impl Color {
     pub fn new(r: f64, g: f64, b: f64) -> Self {
         Self {
-            r: r.min(1.0).max(0.0),
-            g: g.min(1.0).max(0.0),
-            b: b.min(1.0).max(0.0),
+            r: r.clamp(0.0, 1.0),
+            g: g.clamp(0.0, 1.0),
+            b: b.clamp(0.0, 1.0),
         }
     }
 }
@@ -232,9 +263,9 @@ impl std::ops::Mul<f64> for Color {
 
     fn mul(self, rhs: f64) -> Self::Output {
         Self {
-            r: rhs.min(1.0).max(0.0) * self.r,
-            g: rhs.min(1.0).max(0.0) * self.g,
-            b: rhs.min(1.0).max(0.0) * self.b,
+            r: rhs.clamp(0.0, 1.0) * self.r,
+            g: rhs.clamp(0.0, 1.0) * self.g,
+            b: rhs.clamp(0.0, 1.0) * self.b,
         }
     }
 }
@@ -244,18 +275,18 @@ impl std::ops::Add for Color {
 
     fn add(self, rhs: Self) -> Self::Output {
         Self {
-            r: (self.r + rhs.r).min(1.0).max(0.0),
-            g: (self.g + rhs.g).min(1.0).max(0.0),
-            b: (self.b + rhs.b).min(1.0).max(0.0),
+            r: (self.r + rhs.r).clamp(0.0, 1.0),
+            g: (self.g + rhs.g).clamp(0.0, 1.0),
+            b: (self.b + rhs.b).clamp(0.0, 1.0),
         }
     }
 }
 
 impl std::ops::AddAssign for Color {
     fn add_assign(&mut self, rhs: Self) {
-        self.r = (self.r + rhs.r).min(1.0).max(0.0);
-        self.g = (self.g + rhs.g).min(1.0).max(0.0);
-        self.b = (self.b + rhs.b).min(1.0).max(0.0);
+        self.r = (self.r + rhs.r).clamp(0.0, 1.0);
+        self.g = (self.g + rhs.g).clamp(0.0, 1.0);
+        self.b = (self.b + rhs.b).clamp(0.0, 1.0);
     }
 }
 
@@ -264,18 +295,18 @@ impl std::ops::Mul for Color {
 
     fn mul(self, rhs: Self) -> Self::Output {
         Self {
-            r: self.r * rhs.r.min(1.0).max(0.0),
-            g: self.g * rhs.g.min(1.0).max(0.0),
-            b: self.b * rhs.b.min(1.0).max(0.0),
+            r: self.r * rhs.r.clamp(0.0, 1.0),
+            g: self.g * rhs.g.clamp(0.0, 1.0),
+            b: self.b * rhs.b.clamp(0.0, 1.0),
         }
     }
 }
 
 impl std::ops::MulAssign for Color {
     fn mul_assign(&mut self, rhs: Self) {
-        self.r *= rhs.r.min(1.0).max(0.0);
-        self.g *= rhs.g.min(1.0).max(0.0);
-        self.b *= rhs.b.min(1.0).max(0.0);
+        self.r *= rhs.r.clamp(0.0, 1.0);
+        self.g *= rhs.g.clamp(0.0, 1.0);
+        self.b *= rhs.b.clamp(0.0, 1.0);
     }
 }
 
@@ -309,22 +340,407 @@ impl From<[f64; 3]> for Color {
     }
 }
 
-#[derive(Debug, PartialEq, Copy, Clone)]
+#[derive(Clone)]
 pub struct Material {
     color: Color,
+    texture: Option<Arc<dyn Texture + Send + Sync>>,
+    normal_map: Option<Arc<dyn Texture + Send + Sync>>,
+    bump_map: Option<(Arc<dyn Texture + Send + Sync>, f64)>,
+    hair_shininess: Option<f64>,
     reflectivity: f64,
 }
 
 impl Material {
     pub fn new<T: Into<Color>>(color: T, reflectivity: f64) -> Self {
-        Self { color: color.into(), reflectivity }
+        Self { color: color.into(), texture: None, normal_map: None, bump_map: None, hair_shininess: None, reflectivity }
+    }
+
+    /// A material whose color is looked up in `texture` instead of being a single solid color.
+    pub fn textured<T: Texture + Send + Sync + 'static>(texture: T, reflectivity: f64) -> Self {
+        Self { color: Color::new(1.0, 1.0, 1.0), texture: Some(Arc::new(texture)), normal_map: None, bump_map: None, hair_shininess: None, reflectivity }
     }
 
     pub fn get_color(&self) -> &Color {
         &self.color
     }
 
+    /// The color of the material at a point, sampled from the texture if there is one.
+    pub fn color_at(&self, coords: &TextureCoords) -> Color {
+        match &self.texture {
+            Some(texture) => texture.color_at(coords),
+            None => self.color,
+        }
+    }
+
+    /// Adds a tangent space normal map, where red, green and blue map to the tangent, bitangent and normal.
+    pub fn with_normal_map<T: Texture + Send + Sync + 'static>(mut self, normal_map: T) -> Self {
+        self.normal_map = Some(Arc::new(normal_map));
+        self
+    }
+
+    /// Adds a bump map whose brightness is used as surface height, scaled by `strength`.
+    pub fn with_bump_map<T: Texture + Send + Sync + 'static>(mut self, height: T, strength: f64) -> Self {
+        self.bump_map = Some((Arc::new(height), strength));
+        self
+    }
+
+    /// Lights the surface as strands of hair running along the tangent (Kajiya-Kay), with a highlight
+    /// that narrows as `shininess` grows.
+    pub fn with_hair_shading(mut self, shininess: f64) -> Self {
+        self.hair_shininess = Some(shininess);
+        self
+    }
+
+    /// How strongly a light in direction `to_light` lights the surface as seen from `to_viewer`,
+    /// both unit vectors. Hair is lit by the sine of its angle to the light, plus a highlight where
+    /// the halfway vector is perpendicular to the strand.
+    pub fn light_factor(&self, shading_normal: &Vector3, tangent: &Vector3, to_light: &Vector3, to_viewer: &Vector3) -> f64 {
+        match self.hair_shininess {
+            None => to_light.dot(shading_normal.norm()).max(0.0),
+            Some(shininess) => {
+                let tangent = tangent.norm();
+                let sine = |v: Vector3| (1.0 - tangent.dot(v).powi(2)).max(0.0).sqrt();
+                sine(*to_light) + sine((*to_light + *to_viewer).norm()).powf(shininess)
+            }
+        }
+    }
+
+    /// The normal used for lighting at a point, after applying the normal and bump maps to the surface normal.
+    /// `tangent` should point in the direction of increasing u and `bitangent` in the direction of increasing v.
+    pub fn shading_normal(&self, normal: &Vector3, tangent: &Vector3, bitangent: &Vector3, coords: &TextureCoords) -> Vector3 {
+        if self.normal_map.is_none() && self.bump_map.is_none() {
+            return *normal;
+        }
+
+        let normal = normal.norm();
+        let mut tangent = *tangent - normal * normal.dot(*tangent);
+        if tangent.mag() < 1e-9 {
+            tangent = normal.perpendicular();
+        }
+        let tangent = tangent.norm();
+        let mut bitangent = *bitangent - normal * normal.dot(*bitangent) - tangent * tangent.dot(*bitangent);
+        if bitangent.mag() < 1e-9 {
+            bitangent = normal.cross(tangent);
+        }
+        let bitangent = bitangent.norm();
+
+        let mut shading_normal = normal;
+        if let Some(normal_map) = &self.normal_map {
+            let mapped: Vector3 = Vector3::from(normal_map.color_at(coords)) * 2.0 - Vector3::new(1.0, 1.0, 1.0);
+            shading_normal = (tangent * mapped.x + bitangent * mapped.y + normal * mapped.z).norm();
+        }
+        if let Some((height, strength)) = &self.bump_map {
+            // Finite differences of the height along the tangent and bitangent
+            const DELTA: f64 = 0.001;
+            let height_at = |coords: &TextureCoords| {
+                let col = Vector3::from(height.color_at(coords));
+                (col.x + col.y + col.z) / 3.0
+            };
+            let h = height_at(coords);
+            let dh_du = (height_at(&coords.shifted((DELTA, 0.0), tangent * DELTA)) - h) / DELTA;
+            let dh_dv = (height_at(&coords.shifted((0.0, DELTA), bitangent * DELTA)) - h) / DELTA;
+            let t = tangent - shading_normal * shading_normal.dot(tangent);
+            let b = bitangent - shading_normal * shading_normal.dot(bitangent);
+            shading_normal = (shading_normal - (t * dh_du + b * dh_dv) * *strength).norm();
+        }
+
+        shading_normal
+    }
+
     pub fn get_reflectivity(&self) -> f64 {
         self.reflectivity
     }
-}
\ No newline at end of file
+}
+
+impl std::fmt::Debug for Material {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), Error> {
+        f.debug_struct("Material")
+            .field("color", &self.color)
+            .field("textured", &self.texture.is_some())
+            .field("normal_mapped", &self.normal_map.is_some())
+            .field("bump_mapped", &self.bump_map.is_some())
+            .field("hair_shininess", &self.hair_shininess)
+            .field("reflectivity", &self.reflectivity)
+            .finish()
+    }
+}
+
+impl PartialEq for Material {
+    fn eq(&self, other: &Self) -> bool {
+        fn same_texture(a: &Option<Arc<dyn Texture + Send + Sync>>, b: &Option<Arc<dyn Texture + Send + Sync>>) -> bool {
+            match (a, b) {
+                (Some(a), Some(b)) => Arc::ptr_eq(a, b),
+                (None, None) => true,
+                _ => false,
+            }
+        }
+        let same_bump_map = match (&self.bump_map, &other.bump_map) {
+            (Some(a), Some(b)) => Arc::ptr_eq(&a.0, &b.0) && a.1 == b.1,
+            (None, None) => true,
+            _ => false,
+        };
+        self.color == other.color && self.reflectivity == other.reflectivity && same_texture(&self.texture, &other.texture)
+            && same_texture(&self.normal_map, &other.normal_map) && same_bump_map && self.hair_shininess == other.hair_shininess
+    }
+}
+
+/// A 4x4 matrix in row major order, acting on column vectors.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Matrix4 {
+    m: [[f64; 4]; 4],
+}
+
+impl Matrix4 {
+    pub fn new(m: [[f64; 4]; 4]) -> Self {
+        Self { m }
+    }
+
+    pub fn identity() -> Self {
+        Self::new([
+            [1.0, 0.0, 0.0, 0.0],
+            [0.0, 1.0, 0.0, 0.0],
+            [0.0, 0.0, 1.0, 0.0],
+            [0.0, 0.0, 0.0, 1.0],
+        ])
+    }
+
+    pub fn translation<T: Into<Vector3>>(offset: T) -> Self {
+        let o = offset.into();
+        Self::new([
+            [1.0, 0.0, 0.0, o.x],
+            [0.0, 1.0, 0.0, o.y],
+            [0.0, 0.0, 1.0, o.z],
+            [0.0, 0.0, 0.0, 1.0],
+        ])
+    }
+
+    pub fn scaling<T: Into<Vector3>>(factors: T) -> Self {
+        let f = factors.into();
+        Self::new([
+            [f.x, 0.0, 0.0, 0.0],
+            [0.0, f.y, 0.0, 0.0],
+            [0.0, 0.0, f.z, 0.0],
+            [0.0, 0.0, 0.0, 1.0],
+        ])
+    }
+
+    /// Counterclockwise rotation of `angle` radians around `axis` through the origin.
+    pub fn rotation<T: Into<Vector3>>(axis: T, angle: f64) -> Self {
+        let a = axis.into().norm();
+        let (sin, cos) = angle.sin_cos();
+        let k = 1.0 - cos;
+        Self::new([
+            [cos + a.x * a.x * k, a.x * a.y * k - a.z * sin, a.x * a.z * k + a.y * sin, 0.0],
+            [a.y * a.x * k + a.z * sin, cos + a.y * a.y * k, a.y * a.z * k - a.x * sin, 0.0],
+            [a.z * a.x * k - a.y * sin, a.z * a.y * k + a.x * sin, cos + a.z * a.z * k, 0.0],
+            [0.0, 0.0, 0.0, 1.0],
+        ])
+    }
+
+    pub fn transpose(&self) -> Self {
+        let mut m = [[0.0; 4]; 4];
+        for (i, row) in m.iter_mut().enumerate() {
+            for (j, value) in row.iter_mut().enumerate() {
+                *value = self.m[j][i];
+            }
+        }
+        Self::new(m)
+    }
+
+    /// The inverse matrix, or `None` if the matrix is singular or has entries that aren't finite.
+    pub fn inverse(&self) -> Option<Self> {
+        if self.m.iter().flatten().any(|value| !value.is_finite()) {
+            return None;
+        }
+        // Gauss-Jordan elimination with partial pivoting
+        let mut a = self.m;
+        let mut inv = Self::identity().m;
+        for col in 0..4 {
+            let pivot = (col..4).max_by(|&i, &j| a[i][col].abs().total_cmp(&a[j][col].abs())).unwrap();
+            if a[pivot][col].abs() < 1e-12 {
+                return None;
+            }
+            a.swap(col, pivot);
+            inv.swap(col, pivot);
+
+            let scale = a[col][col];
+            for j in 0..4 {
+                a[col][j] /= scale;
+                inv[col][j] /= scale;
+            }
+            for row in 0..4 {
+                if row != col {
+                    let factor = a[row][col];
+                    for j in 0..4 {
+                        a[row][j] -= factor * a[col][j];
+                        inv[row][j] -= factor * inv[col][j];
+                    }
+                }
+            }
+        }
+        Some(Self::new(inv))
+    }
+
+    pub fn transform_point(&self, p: &Vector3) -> Vector3 {
+        let m = &self.m;
+        let w = m[3][0] * p.x + m[3][1] * p.y + m[3][2] * p.z + m[3][3];
+        Vector3::new(
+            m[0][0] * p.x + m[0][1] * p.y + m[0][2] * p.z + m[0][3],
+            m[1][0] * p.x + m[1][1] * p.y + m[1][2] * p.z + m[1][3],
+            m[2][0] * p.x + m[2][1] * p.y + m[2][2] * p.z + m[2][3],
+        ) / w
+    }
+
+    /// Transforms a direction, ignoring the translation.
+    pub fn transform_vector(&self, v: &Vector3) -> Vector3 {
+        let m = &self.m;
+        Vector3::new(
+            m[0][0] * v.x + m[0][1] * v.y + m[0][2] * v.z,
+            m[1][0] * v.x + m[1][1] * v.y + m[1][2] * v.z,
+            m[2][0] * v.x + m[2][1] * v.y + m[2][2] * v.z,
+        )
+    }
+}
+
+impl std::ops::Mul for Matrix4 {
+    type Output = Self;
+
+    fn mul(self, rhs: Self) -> Self::Output {
+        let mut m = [[0.0; 4]; 4];
+        for (i, row) in m.iter_mut().enumerate() {
+            for (j, value) in row.iter_mut().enumerate() {
+                *value = (0..4).map(|k| self.m[i][k] * rhs.m[k][j]).sum();
+            }
+        }
+        Self::new(m)
+    }
+}
+
+/// An invertible affine transformation, keeping its inverse around for transforming rays and normals.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Transform {
+    matrix: Matrix4,
+    inverse: Matrix4,
+}
+
+impl Transform {
+    /// Panics if `matrix` can't be inverted.
+    pub fn new(matrix: Matrix4) -> Self {
+        Self { matrix, inverse: matrix.inverse().expect("transform matrix isn't invertible") }
+    }
+
+    pub fn identity() -> Self {
+        Self { matrix: Matrix4::identity(), inverse: Matrix4::identity() }
+    }
+
+    pub fn translate<T: Into<Vector3>>(offset: T) -> Self {
+        let offset = offset.into();
+        Self { matrix: Matrix4::translation(offset), inverse: Matrix4::translation(-offset) }
+    }
+
+    pub fn rotate<T: Into<Vector3>>(axis: T, angle: f64) -> Self {
+        let rotation = Matrix4::rotation(axis, angle);
+        Self { matrix: rotation, inverse: rotation.transpose() }
+    }
+
+    /// Panics if any of the factors is zero.
+    pub fn scale<T: Into<Vector3>>(factors: T) -> Self {
+        let f = factors.into();
+        assert!(f.x != 0.0 && f.y != 0.0 && f.z != 0.0, "can't scale by zero");
+        Self { matrix: Matrix4::scaling(f), inverse: Matrix4::scaling((1.0 / f.x, 1.0 / f.y, 1.0 / f.z)) }
+    }
+
+    /// The transformation applying `self` first and then `next`.
+    pub fn then(&self, next: &Transform) -> Self {
+        Self { matrix: next.matrix * self.matrix, inverse: self.inverse * next.inverse }
+    }
+
+    pub fn inverse(&self) -> Self {
+        Self { matrix: self.inverse, inverse: self.matrix }
+    }
+
+    pub fn get_matrix(&self) -> &Matrix4 {
+        &self.matrix
+    }
+
+    pub fn point(&self, p: &Vector3) -> Vector3 {
+        self.matrix.transform_point(p)
+    }
+
+    pub fn vector(&self, v: &Vector3) -> Vector3 {
+        self.matrix.transform_vector(v)
+    }
+
+    /// Transforms a surface normal, which needs the inverse transpose to stay perpendicular to the
+    /// surface under non-uniform scaling. The result isn't normalized.
+    pub fn normal(&self, n: &Vector3) -> Vector3 {
+        self.inverse.transpose().transform_vector(n)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::procedural::Gradient;
+
+    #[test]
+    fn test_shading_normal() {
+        let normal = Vector3::new(0.0, 0.0, 1.0);
+        let tangent = Vector3::new(1.0, 0.0, 0.0);
+        let bitangent = Vector3::new(0.0, 1.0, 0.0);
+        let coords = TextureCoords::new((0.0, 0.0), (0.0, 0.0, 0.0), (0.0, 0.0, 0.0));
+
+        let flat = Material::new((1.0, 1.0, 1.0), 0.0).with_normal_map(Gradient::new((0.5, 0.5, 1.0), (0.5, 0.5, 1.0), (0.0, 0.0, 0.0), (1.0, 0.0, 0.0), 1.0));
+        assert!((flat.shading_normal(&normal, &tangent, &bitangent, &coords) - normal).mag() < 1e-9);
+
+        // Height rising along the tangent tilts the normal back against it
+        let slope = Material::new((1.0, 1.0, 1.0), 0.0).with_bump_map(Gradient::new((0.0, 0.0, 0.0), (1.0, 1.0, 1.0), (-1.0, 0.0, 0.0), (1.0, 0.0, 0.0), 2.0), 1.0);
+        let bumped = slope.shading_normal(&normal, &tangent, &bitangent, &coords);
+        assert!((bumped - Vector3::new(-0.5, 0.0, 1.0).norm()).mag() < 1e-6);
+    }
+
+    #[test]
+    fn test_hair_shading() {
+        let normal = Vector3::new(0.0, 0.0, -1.0);
+        let tangent = Vector3::new(1.0, 0.0, 0.0);
+        let viewer = Vector3::new(0.0, 0.0, -1.0);
+        let hair = Material::new((1.0, 1.0, 1.0), 0.0).with_hair_shading(20.0);
+        // Lit fully from across the strand, with the highlight on top, and not at all from along it
+        assert!((hair.light_factor(&normal, &tangent, &Vector3::new(0.0, 1.0, 0.0), &viewer) - 2.0).abs() < 1e-9);
+        assert!(hair.light_factor(&normal, &tangent, &Vector3::new(1.0, 0.0, 0.0), &viewer) < 0.01);
+        // Light from behind a surface doesn't reach it, but does light hair
+        let behind = Vector3::new(0.0, 0.0, 1.0);
+        assert_eq!(Material::new((1.0, 1.0, 1.0), 0.0).light_factor(&normal, &tangent, &behind, &viewer), 0.0);
+        assert!(hair.light_factor(&normal, &tangent, &behind, &viewer) >= 1.0);
+    }
+
+    #[test]
+    fn test_matrix_inverse() {
+        let m = Matrix4::translation((1.0, 2.0, 3.0)) * Matrix4::rotation((1.0, 1.0, 0.0), 0.7) * Matrix4::scaling((2.0, 0.5, 3.0));
+        let product = m * m.inverse().unwrap();
+        for i in 0..4 {
+            for j in 0..4 {
+                let expected = if i == j { 1.0 } else { 0.0 };
+                assert!((product.m[i][j] - expected).abs() < 1e-9);
+            }
+        }
+        assert!(Matrix4::scaling((1.0, 0.0, 1.0)).inverse().is_none());
+        assert!(Matrix4::scaling((1.0, f64::NAN, 1.0)).inverse().is_none());
+        assert!(Matrix4::translation((f64::INFINITY, 0.0, 0.0)).inverse().is_none());
+    }
+
+    #[test]
+    fn test_transform() {
+        let transform = Transform::scale((2.0, 1.0, 1.0))
+            .then(&Transform::rotate((0.0, 0.0, 1.0), std::f64::consts::FRAC_PI_2))
+            .then(&Transform::translate((0.0, 0.0, 5.0)));
+        assert!((transform.point(&Vector3::new(1.0, 0.0, 0.0)) - Vector3::new(0.0, 2.0, 5.0)).mag() < 1e-9);
+        assert!((transform.inverse().point(&Vector3::new(0.0, 2.0, 5.0)) - Vector3::new(1.0, 0.0, 0.0)).mag() < 1e-9);
+        assert!((transform.vector(&Vector3::new(0.0, 1.0, 0.0)) - Vector3::new(-1.0, 0.0, 0.0)).mag() < 1e-9);
+
+        // A 45 degree slope squashed to half its width gets a steeper normal
+        let squash = Transform::scale((0.5, 1.0, 1.0));
+        let normal = squash.normal(&Vector3::new(1.0, 1.0, 0.0)).norm();
+        assert!((normal - Vector3::new(2.0, 1.0, 0.0).norm()).mag() < 1e-9);
+    }
+}
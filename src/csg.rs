@@ -0,0 +1,160 @@
+//! Constructive solid geometry: new solids made by combining the insides of closed objects.
+
+use crate::basics::Material;
+use crate::raycast::{Ray, Hit};
+use crate::shapes::{SceneObject, Span};
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum CsgOperation {
+    /// Inside either object
+    Union,
+    /// Inside both objects
+    Intersection,
+    /// Inside the left object but not the right one
+    Difference,
+}
+
+impl CsgOperation {
+    fn contains(self, in_left: bool, in_right: bool) -> bool {
+        match self {
+            CsgOperation::Union => in_left || in_right,
+            CsgOperation::Intersection => in_left && in_right,
+            CsgOperation::Difference => in_left && !in_right,
+        }
+    }
+}
+
+/// Two closed objects combined into one solid. Each part of the surface keeps the material of the
+/// object it came from, so the walls of a hole cut with a difference show the cutting object's
+/// material. Open objects like quads have no inside and act as if they were empty.
+pub struct Csg {
+    operation: CsgOperation,
+    left: Box<dyn SceneObject + Send + Sync>,
+    right: Box<dyn SceneObject + Send + Sync>,
+}
+
+impl Csg {
+    pub fn new(operation: CsgOperation, left: Box<dyn SceneObject + Send + Sync>, right: Box<dyn SceneObject + Send + Sync>) -> Self {
+        Self { operation, left, right }
+    }
+
+    pub fn union(left: Box<dyn SceneObject + Send + Sync>, right: Box<dyn SceneObject + Send + Sync>) -> Self {
+        Self::new(CsgOperation::Union, left, right)
+    }
+
+    pub fn intersection(left: Box<dyn SceneObject + Send + Sync>, right: Box<dyn SceneObject + Send + Sync>) -> Self {
+        Self::new(CsgOperation::Intersection, left, right)
+    }
+
+    pub fn difference(left: Box<dyn SceneObject + Send + Sync>, right: Box<dyn SceneObject + Send + Sync>) -> Self {
+        Self::new(CsgOperation::Difference, left, right)
+    }
+
+    pub fn get_operation(&self) -> CsgOperation {
+        self.operation
+    }
+}
+
+impl SceneObject for Csg {
+    fn ray_intersects(&self, ray: &Ray) -> Option<Hit<'_>> {
+        self.intervals(ray).into_iter()
+            .flat_map(|(entry, exit)| vec![entry, exit])
+            .find(|hit| ray.contains(hit.get_t()))
+    }
+
+    fn intervals(&self, ray: &Ray) -> Vec<Span<'_>> {
+        // Walk through both objects' crossings in order, tracking which insides the ray is in
+        let mut crossings: Vec<(Hit<'_>, bool, bool)> = Vec::new();
+        for (entry, exit) in self.left.intervals(ray) {
+            crossings.push((entry, true, true));
+            crossings.push((exit, true, false));
+        }
+        for (entry, exit) in self.right.intervals(ray) {
+            crossings.push((entry, false, true));
+            crossings.push((exit, false, false));
+        }
+        crossings.sort_by(|a, b| a.0.get_t().partial_cmp(&b.0.get_t()).unwrap());
+
+        let mut spans = Vec::new();
+        let (mut in_left, mut in_right) = (false, false);
+        let mut entry: Option<Hit<'_>> = None;
+        for (hit, is_left, entering) in crossings {
+            if is_left {
+                in_left = entering;
+            } else {
+                in_right = entering;
+            }
+            match (entry, self.operation.contains(in_left, in_right)) {
+                (None, true) => entry = Some(hit.with_front_face(true)),
+                (Some(start), false) => {
+                    spans.push((start, hit.with_front_face(false)));
+                    entry = None;
+                }
+                _ => {}
+            }
+        }
+        spans
+    }
+
+    fn get_material(&self) -> &Material {
+        self.left.get_material()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::shapes::{Sphere, Cuboid, Quad};
+
+    fn spheres(operation: CsgOperation) -> Csg {
+        // Two unit spheres overlapping between z = 9.5 and z = 10.5
+        let material = Material::new((1.0, 0.0, 0.0), 0.0);
+        Csg::new(
+            operation,
+            Box::new(Sphere::new(1.0, (0.0, 0.0, 9.5), material.clone())),
+            Box::new(Sphere::new(1.0, (0.0, 0.0, 10.5), material)),
+        )
+    }
+
+    fn span_distances(csg: &Csg, ray: &Ray) -> Vec<(f64, f64)> {
+        csg.intervals(ray).iter().map(|(entry, exit)| (entry.get_t(), exit.get_t())).collect()
+    }
+
+    #[test]
+    fn test_csg_spans() {
+        let ray = Ray::new((0.0, 0.0, 0.0), (0.0, 0.0, 1.0));
+        assert_eq!(span_distances(&spheres(CsgOperation::Union), &ray), vec![(8.5, 11.5)]);
+        assert_eq!(span_distances(&spheres(CsgOperation::Intersection), &ray), vec![(9.5, 10.5)]);
+        assert_eq!(span_distances(&spheres(CsgOperation::Difference), &ray), vec![(8.5, 9.5)]);
+    }
+
+    #[test]
+    fn test_csg_intersect() {
+        let difference = spheres(CsgOperation::Difference);
+        // From behind, the first surface is the bite taken out by the right sphere
+        let back = difference.ray_intersects(&Ray::new((0.0, 0.0, 20.0), (0.0, 0.0, -1.0))).unwrap();
+        assert!((back.get_t() - 10.5).abs() < 1e-9);
+        assert!(back.is_front_face());
+        assert!((*back.get_normal() - crate::basics::Vector3::new(0.0, 0.0, 1.0)).mag() < 1e-9);
+
+        // Starting inside the solid, the ray leaves through the back face
+        let inside = difference.ray_intersects(&Ray::new((0.0, 0.0, 9.0), (0.0, 0.0, 1.0))).unwrap();
+        assert!((inside.get_t() - 0.5).abs() < 1e-9);
+        assert!(!inside.is_front_face());
+
+        // A box with a sphere cut out of its middle lets a ray through the hole pass the cavity
+        let material = Material::new((1.0, 0.0, 0.0), 0.0);
+        let hollow = Csg::difference(
+            Box::new(Cuboid::new((-1.0, -1.0, 9.0), (1.0, 1.0, 11.0), material.clone())),
+            Box::new(Sphere::new(0.8, (0.0, 0.0, 10.0), material.clone())),
+        );
+        let ray = Ray::new((0.0, 0.0, 0.0), (0.0, 0.0, 1.0));
+        let spans = span_distances(&hollow, &ray);
+        assert_eq!(spans.len(), 2);
+        assert!((spans[0].1 - 9.2).abs() < 1e-9 && (spans[1].0 - 10.8).abs() < 1e-9);
+
+        // Open shapes have no inside
+        let open = Csg::union(Box::new(Quad::new((-1.0, -1.0, 5.0), (2.0, 0.0, 0.0), (0.0, 2.0, 0.0), material.clone())), Box::new(Quad::new((-1.0, -1.0, 6.0), (2.0, 0.0, 0.0), (0.0, 2.0, 0.0), material)));
+        assert!(open.ray_intersects(&ray).is_none());
+    }
+}
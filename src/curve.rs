@@ -0,0 +1,320 @@
+use crate::basics::{Vector3, Material};
+use crate::raycast::{Ray, Hit};
+use crate::shapes::SceneObject;
+
+/// Deepest the Bezier pieces are split before being treated as straight.
+const MAX_DEPTH: u32 = 10;
+
+/// How a curve's width is turned into a surface.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum CurveMode {
+    /// A flat strip that always faces the incoming ray, cheap enough for lots of fur or grass.
+    Ribbon,
+    /// A round tube, for strands seen up close.
+    Tube,
+}
+
+/// A piece of the curve as a cubic Bezier in ray space, where the ray runs along +z from the
+/// origin, with its widths at the Bezier control points and its span of the curve's parameter.
+#[derive(Copy, Clone, Debug)]
+struct Piece {
+    points: [Vector3; 4],
+    widths: [f64; 4],
+    span: (f64, f64),
+}
+
+impl Piece {
+    fn split(&self) -> (Piece, Piece) {
+        let half = |a: Vector3, b: Vector3| (a + b) / 2.0;
+        let p = &self.points;
+        let (p01, p12, p23) = (half(p[0], p[1]), half(p[1], p[2]), half(p[2], p[3]));
+        let (p012, p123) = (half(p01, p12), half(p12, p23));
+        let middle = half(p012, p123);
+
+        let w = &self.widths;
+        let (w01, w12, w23) = ((w[0] + w[1]) / 2.0, (w[1] + w[2]) / 2.0, (w[2] + w[3]) / 2.0);
+        let (w012, w123) = ((w01 + w12) / 2.0, (w12 + w23) / 2.0);
+        let w_middle = (w012 + w123) / 2.0;
+
+        let u_middle = (self.span.0 + self.span.1) / 2.0;
+        (
+            Piece { points: [p[0], p01, p012, middle], widths: [w[0], w01, w012, w_middle], span: (self.span.0, u_middle) },
+            Piece { points: [middle, p123, p23, p[3]], widths: [w_middle, w123, w23, w[3]], span: (u_middle, self.span.1) },
+        )
+    }
+}
+
+/// Where a ray meets a curve: depth along the ray, curve parameter and the across the width
+/// coordinate from 0 to 1.
+type CurveCrossing = (f64, f64, f64);
+
+/// A thin strand following a uniform cubic B-spline through its control points, with a width
+/// per control point blended the same way. Each run of four control points makes one segment, so
+/// repeat the first and last points to have the curve reach them.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Curve {
+    points: Vec<Vector3>,
+    widths: Vec<f64>,
+    bounds: (Vector3, Vector3),
+    mode: CurveMode,
+    material: Material,
+}
+
+impl Curve {
+    pub fn new(mode: CurveMode, material: Material) -> Self {
+        let empty = (Vector3::new(f64::INFINITY, f64::INFINITY, f64::INFINITY), Vector3::new(f64::NEG_INFINITY, f64::NEG_INFINITY, f64::NEG_INFINITY));
+        Self { points: Vec::new(), widths: Vec::new(), bounds: empty, mode, material }
+    }
+
+    pub fn with_point<T: Into<Vector3>>(mut self, point: T, width: f64) -> Self {
+        let point = point.into();
+        self.points.push(point);
+        self.widths.push(width);
+        // The curve stays within the control points' hull, so padding their box by the widest
+        // half width bounds the whole strand
+        let (low, high) = self.bounds;
+        let pad = width / 2.0;
+        self.bounds = (
+            Vector3::new(low.get_x().min(point.get_x() - pad), low.get_y().min(point.get_y() - pad), low.get_z().min(point.get_z() - pad)),
+            Vector3::new(high.get_x().max(point.get_x() + pad), high.get_y().max(point.get_y() + pad), high.get_z().max(point.get_z() + pad)),
+        );
+        self
+    }
+
+    /// Whether the ray passes through the bounding box within its interval.
+    fn in_bounds(&self, ray: &Ray) -> bool {
+        let (low, high) = self.bounds;
+        let (origin, direction) = (ray.get_origin(), ray.get_direction());
+        let mut near = ray.get_t_min();
+        let mut far = ray.get_t_max();
+        for (o, d, l, h) in [
+            (origin.get_x(), direction.get_x(), low.get_x(), high.get_x()),
+            (origin.get_y(), direction.get_y(), low.get_y(), high.get_y()),
+            (origin.get_z(), direction.get_z(), low.get_z(), high.get_z()),
+        ] {
+            if d.abs() < 1e-12 {
+                if o < l || o > h {
+                    return false;
+                }
+                continue;
+            }
+            let (t1, t2) = ((l - o) / d, (h - o) / d);
+            near = near.max(t1.min(t2));
+            far = far.min(t1.max(t2));
+        }
+        near <= far
+    }
+
+    pub fn get_mode(&self) -> CurveMode {
+        self.mode
+    }
+
+    fn segment_count(&self) -> usize {
+        self.points.len().saturating_sub(3)
+    }
+
+    /// Bezier control points and widths of a segment.
+    fn bezier(&self, segment: usize) -> ([Vector3; 4], [f64; 4]) {
+        let p = &self.points[segment..segment + 4];
+        let w = &self.widths[segment..segment + 4];
+        (
+            [(p[0] + p[1] * 4.0 + p[2]) / 6.0, (p[1] * 2.0 + p[2]) / 3.0, (p[1] + p[2] * 2.0) / 3.0, (p[1] + p[2] * 4.0 + p[3]) / 6.0],
+            [(w[0] + w[1] * 4.0 + w[2]) / 6.0, (w[1] * 2.0 + w[2]) / 3.0, (w[1] + w[2] * 2.0) / 3.0, (w[1] + w[2] * 4.0 + w[3]) / 6.0],
+        )
+    }
+
+    /// The point on the curve at parameter `u` from 0 to 1 and the direction it runs in there, or
+    /// nothing if the curve has fewer than four points and so no segments yet.
+    pub fn point(&self, u: f64) -> Option<(Vector3, Vector3)> {
+        let segments = self.segment_count();
+        if segments == 0 {
+            return None;
+        }
+        let scaled = u.clamp(0.0, 1.0) * segments as f64;
+        let segment = (scaled.floor() as usize).min(segments - 1);
+        let (p, _) = self.bezier(segment);
+        let t = scaled - segment as f64;
+        let s = 1.0 - t;
+        let point = p[0] * (s * s * s) + p[1] * (3.0 * t * s * s) + p[2] * (3.0 * t * t * s) + p[3] * (t * t * t);
+        let direction = (p[1] - p[0]) * (s * s) + (p[2] - p[1]) * (2.0 * t * s) + (p[3] - p[2]) * (t * t);
+        Some((point, direction))
+    }
+
+    /// Splits a piece in two until it's close enough to straight, skipping halves whose bounds
+    /// the ray misses, and keeps the nearest crossing.
+    fn cross(&self, piece: &Piece, depth: u32, range: (f64, f64), closest: &mut Option<CurveCrossing>) {
+        let radius = piece.widths.iter().fold(0.0, |a: f64, &b| a.max(b)) / 2.0;
+        let far = closest.map_or(range.1, |c| c.0);
+        let (low, high) = piece.points.iter().fold((Vector3::new(f64::INFINITY, f64::INFINITY, f64::INFINITY), Vector3::new(f64::NEG_INFINITY, f64::NEG_INFINITY, f64::NEG_INFINITY)), |(low, high), p| {
+            (
+                Vector3::new(low.get_x().min(p.get_x()), low.get_y().min(p.get_y()), low.get_z().min(p.get_z())),
+                Vector3::new(high.get_x().max(p.get_x()), high.get_y().max(p.get_y()), high.get_z().max(p.get_z())),
+            )
+        });
+        if low.get_x() > radius || high.get_x() < -radius || low.get_y() > radius || high.get_y() < -radius
+            || low.get_z() - radius > far || high.get_z() + radius < range.0 {
+            return;
+        }
+
+        if depth > 0 {
+            let (first, second) = piece.split();
+            self.cross(&first, depth - 1, range, closest);
+            self.cross(&second, depth - 1, range, closest);
+            return;
+        }
+
+        // Closest approach of the straightened piece to the ray, looking down the ray
+        let (start, end) = (piece.points[0], piece.points[3]);
+        let along = end - start;
+        let length2 = along.get_x() * along.get_x() + along.get_y() * along.get_y();
+        if length2 < 1e-24 {
+            return;
+        }
+        let s = -(start.get_x() * along.get_x() + start.get_y() * along.get_y()) / length2;
+        if !(0.0..=1.0).contains(&s) {
+            return;
+        }
+        let closest_point = start + along * s;
+        let distance2 = closest_point.get_x() * closest_point.get_x() + closest_point.get_y() * closest_point.get_y();
+        let half_width = (piece.widths[0] + (piece.widths[3] - piece.widths[0]) * s) / 2.0;
+        if distance2 > half_width * half_width {
+            return;
+        }
+
+        let z = match self.mode {
+            CurveMode::Ribbon => closest_point.get_z(),
+            CurveMode::Tube => closest_point.get_z() - (half_width * half_width - distance2).sqrt(),
+        };
+        if z < range.0 || z > far {
+            return;
+        }
+        // Which side of the strand the ray passes, from the 2D cross product of the two, signed so
+        // that v increases along the normal crossed with the tangent
+        let side = (along.get_x() * closest_point.get_y() - along.get_y() * closest_point.get_x()) / length2.sqrt();
+        let u = piece.span.0 + (piece.span.1 - piece.span.0) * s;
+        *closest = Some((z, u, 0.5 + side / (2.0 * half_width)));
+    }
+
+    /// Nearest crossing within the ray's interval, with the depth converted to a distance along the ray.
+    fn crossing(&self, ray: &Ray) -> Option<CurveCrossing> {
+        let segments = self.segment_count();
+        if segments == 0 || !self.in_bounds(ray) {
+            return None;
+        }
+        let speed = ray.get_direction().mag();
+        let forward = *ray.get_direction() / speed;
+        let right = forward.perpendicular();
+        let up = forward.cross(right);
+        let to_ray_space = |p: Vector3| {
+            let offset = p - *ray.get_origin();
+            Vector3::new(offset.dot(right), offset.dot(up), offset.dot(forward))
+        };
+        let range = (ray.get_t_min() * speed, ray.get_t_max() * speed);
+
+        let mut closest = None;
+        for segment in 0..segments {
+            let (points, widths) = self.bezier(segment);
+            let points = points.map(to_ray_space);
+            // Enough splits to bring the piece within a twentieth of its width of straight
+            let bend = (0..2).map(|i| (points[i] - points[i + 1] * 2.0 + points[i + 2]).mag()).fold(0.0, f64::max);
+            let width = widths.iter().fold(0.0, |a: f64, &b| a.max(b)).max(1e-12);
+            let depth = ((6.0 * bend * 20.0 / (8.0 * width)).max(1.0).log2() / 2.0).ceil() as u32;
+            let span = (segment as f64 / segments as f64, (segment + 1) as f64 / segments as f64);
+            self.cross(&Piece { points, widths, span }, depth.min(MAX_DEPTH), range, &mut closest);
+        }
+        closest.map(|(z, u, v)| (z / speed, u, v))
+    }
+}
+
+impl SceneObject for Curve {
+    fn ray_intersects(&self, ray: &Ray) -> Option<Hit<'_>> {
+        let (t, u, v) = self.crossing(ray)?;
+        let (axis, direction) = self.point(u)?;
+        let tangent = direction.norm();
+        let normal = match self.mode {
+            // Faces back along the ray, turned to lie across the strand
+            CurveMode::Ribbon => {
+                let back = -ray.get_direction().norm();
+                let across = back - tangent * tangent.dot(back);
+                if across.mag() > 1e-9 { across } else { back }
+            }
+            CurveMode::Tube => ray.at(t) - axis,
+        };
+        Some(Hit::new(ray, t, normal, &self.material)
+            .with_tangent(tangent)
+            .with_bitangent(normal.norm().cross(tangent))
+            .with_uv((u, v)))
+    }
+
+    fn occludes(&self, ray: &Ray) -> bool {
+        self.crossing(ray).is_some()
+    }
+
+    fn get_material(&self) -> &Material {
+        &self.material
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// A straight strand along x at z = 10. Evenly spaced control points from -3 to 3 make a
+    /// single segment from -1 to 1.
+    fn strand(mode: CurveMode, width: f64) -> Curve {
+        let material = Material::new((1.0, 1.0, 1.0), 0.0);
+        (0..4).fold(Curve::new(mode, material), |curve, i| curve.with_point((-3.0 + 2.0 * i as f64, 0.0, 10.0), width))
+    }
+
+    #[test]
+    fn test_ribbon_intersect() {
+        let ribbon = strand(CurveMode::Ribbon, 0.2);
+        let hit = ribbon.ray_intersects(&Ray::new((0.5, 0.05, 0.0), (0.0, 0.0, 1.0))).unwrap();
+        assert!((hit.get_t() - 10.0).abs() < 1e-9);
+        assert!((*hit.get_normal() - Vector3::new(0.0, 0.0, -1.0)).mag() < 1e-9);
+        assert!((*hit.get_tangent() - Vector3::new(1.0, 0.0, 0.0)).mag() < 1e-9);
+        assert!((hit.get_uv().0 - 0.75).abs() < 1e-6);
+        // v runs across the strand towards the bitangent
+        assert!((hit.get_uv().1 - 0.25).abs() < 1e-6);
+        assert!((hit.get_bitangent() - Vector3::new(0.0, -1.0, 0.0)).mag() < 1e-9);
+        assert!(ribbon.ray_intersects(&Ray::new((0.5, 0.15, 0.0), (0.0, 0.0, 1.0))).is_none());
+        assert!(ribbon.ray_intersects(&Ray::new((1.5, 0.0, 0.0), (0.0, 0.0, 1.0))).is_none());
+        assert!(!ribbon.occludes(&Ray::new((0.5, 0.0, 0.0), (0.0, 0.0, 1.0)).with_interval(0.0, 9.0)));
+    }
+
+    #[test]
+    fn test_tube_intersect() {
+        let tube = strand(CurveMode::Tube, 1.0);
+        let hit = tube.ray_intersects(&Ray::new((0.0, 0.3, 0.0), (0.0, 0.0, 1.0))).unwrap();
+        let depth = (0.25f64 - 0.09).sqrt();
+        assert!((hit.get_t() - (10.0 - depth)).abs() < 1e-9);
+        assert!((*hit.get_normal() - Vector3::new(0.0, 0.3, -depth) / 0.5).mag() < 1e-9);
+    }
+
+    #[test]
+    fn test_axis_parallel_bounds() {
+        // Runs along the top face of the bounding box, with no x or y movement
+        let ribbon = strand(CurveMode::Ribbon, 0.2);
+        assert!(ribbon.in_bounds(&Ray::new((0.5, 0.1, 0.0), (0.0, 0.0, 1.0))));
+        assert!(!ribbon.in_bounds(&Ray::new((0.5, 0.2, 0.0), (0.0, 0.0, 1.0))));
+        assert!(ribbon.in_bounds(&Ray::new((-5.0, 0.0, 10.0), (1.0, 0.0, 0.0))));
+    }
+
+    #[test]
+    fn test_curved_strand() {
+        // A bent strand is hit where it actually runs, not along the chord of its ends
+        let material = Material::new((1.0, 1.0, 1.0), 0.0);
+        let arc = Curve::new(CurveMode::Ribbon, material)
+            .with_point((-2.0, 0.0, 10.0), 0.05).with_point((-2.0, 0.0, 10.0), 0.05)
+            .with_point((0.0, 2.0, 10.0), 0.05)
+            .with_point((2.0, 0.0, 10.0), 0.05).with_point((2.0, 0.0, 10.0), 0.05);
+        let (top, _) = arc.point(0.5).unwrap();
+        assert!(arc.ray_intersects(&Ray::new((top.get_x(), top.get_y(), 0.0), (0.0, 0.0, 1.0))).is_some());
+        assert!(arc.ray_intersects(&Ray::new((0.0, 0.0, 0.0), (0.0, 0.0, 1.0))).is_none());
+
+        let short = Curve::new(CurveMode::Tube, Material::new((1.0, 1.0, 1.0), 0.0))
+            .with_point((0.0, 0.0, 10.0), 0.05).with_point((1.0, 0.0, 10.0), 0.05).with_point((2.0, 0.0, 10.0), 0.05);
+        assert!(short.point(0.5).is_none());
+        assert!(short.ray_intersects(&Ray::new((1.0, 0.0, 0.0), (0.0, 0.0, 1.0))).is_none());
+    }
+}
@@ -0,0 +1,203 @@
+//! Distance estimators for 3D fractals, to be rendered with `SdfObject`. Each one reports an
+//! orbit trap between 0 and 1 as its u texture coordinate, so a texture sampled by uv, like a one
+//! row `ImageTexture` palette with clamped wrapping, colors the surface by it.
+
+use crate::basics::Vector3;
+use crate::sdf::DistanceField;
+
+/// The Mandelbulb: points whose orbit stays bounded when repeatedly raised to a power in
+/// spherical coordinates and offset by the starting point. Fits within a radius of about 1.2.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Mandelbulb {
+    power: f64,
+    iterations: usize,
+    bailout: f64,
+}
+
+impl Mandelbulb {
+    /// The classic bulb has power 8.
+    pub fn new(power: f64) -> Self {
+        Self { power, iterations: 12, bailout: 2.0 }
+    }
+
+    pub fn with_iterations(mut self, iterations: usize) -> Self {
+        self.iterations = iterations;
+        self
+    }
+
+    /// The distance estimate and the smallest distance from the origin the orbit came to.
+    fn estimate(&self, p: &Vector3) -> (f64, f64) {
+        let mut z = *p;
+        let mut dr = 1.0;
+        let mut r = z.mag();
+        let mut trap = r;
+        for _ in 0..self.iterations {
+            if r > self.bailout {
+                break;
+            }
+            let theta = (z.get_z() / r.max(1e-12)).clamp(-1.0, 1.0).acos() * self.power;
+            let phi = z.get_y().atan2(z.get_x()) * self.power;
+            dr = r.powf(self.power - 1.0) * self.power * dr + 1.0;
+            let zr = r.powf(self.power);
+            z = Vector3::new(theta.sin() * phi.cos(), phi.sin() * theta.sin(), theta.cos()) * zr + *p;
+            r = z.mag();
+            trap = trap.min(r);
+        }
+        (0.5 * r.max(1e-12).ln() * r / dr, trap)
+    }
+}
+
+impl DistanceField for Mandelbulb {
+    fn distance(&self, p: &Vector3) -> f64 {
+        self.estimate(p).0
+    }
+
+    fn uv(&self, p: &Vector3) -> (f64, f64) {
+        (self.estimate(p).1.clamp(0.0, 1.0), 0.5)
+    }
+}
+
+/// The Menger sponge: a cube from -1 to 1 with the middle of every face repeatedly punched
+/// through on smaller and smaller scales.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct MengerSponge {
+    iterations: usize,
+}
+
+impl MengerSponge {
+    pub fn new(iterations: usize) -> Self {
+        Self { iterations }
+    }
+
+    /// The distance estimate and how far into the iterations the hole closest to `p` was punched.
+    fn estimate(&self, p: &Vector3) -> (f64, f64) {
+        let q = Vector3::new(p.get_x().abs(), p.get_y().abs(), p.get_z().abs()) - Vector3::new(1.0, 1.0, 1.0);
+        let outside = Vector3::new(q.get_x().max(0.0), q.get_y().max(0.0), q.get_z().max(0.0)).mag();
+        let mut distance = outside + q.get_x().max(q.get_y()).max(q.get_z()).min(0.0);
+        let mut trap = 0.0;
+
+        let mut scale = 1.0;
+        for i in 0..self.iterations {
+            // Position within the current cell of the 3x3x3 grid, folded onto the cross shaped hole
+            let cell = |x: f64| 1.0 - 3.0 * ((x * scale).rem_euclid(2.0) - 1.0).abs();
+            let (x, y, z) = (cell(p.get_x()).abs(), cell(p.get_y()).abs(), cell(p.get_z()).abs());
+            scale *= 3.0;
+            let hole = (x.max(y).min(y.max(z)).min(z.max(x)) - 1.0) / scale;
+            if hole > distance {
+                distance = hole;
+                trap = (i + 1) as f64 / self.iterations as f64;
+            }
+        }
+        (distance, trap)
+    }
+}
+
+impl DistanceField for MengerSponge {
+    fn distance(&self, p: &Vector3) -> f64 {
+        self.estimate(p).0
+    }
+
+    fn uv(&self, p: &Vector3) -> (f64, f64) {
+        (self.estimate(p).1, 0.5)
+    }
+}
+
+type Quaternion = [f64; 4];
+
+fn quaternion_product(a: &Quaternion, b: &Quaternion) -> Quaternion {
+    [
+        a[0] * b[0] - a[1] * b[1] - a[2] * b[2] - a[3] * b[3],
+        a[0] * b[1] + a[1] * b[0] + a[2] * b[3] - a[3] * b[2],
+        a[0] * b[2] - a[1] * b[3] + a[2] * b[0] + a[3] * b[1],
+        a[0] * b[3] + a[1] * b[2] - a[2] * b[1] + a[3] * b[0],
+    ]
+}
+
+fn quaternion_length(q: &Quaternion) -> f64 {
+    q.iter().map(|x| x * x).sum::<f64>().sqrt()
+}
+
+/// A 3D slice through a quaternion Julia set: points whose orbit under `z^2 + c` stays bounded,
+/// with the fourth component of the starting point set to zero.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct QuaternionJulia {
+    c: Quaternion,
+    iterations: usize,
+    bailout: f64,
+}
+
+impl QuaternionJulia {
+    pub fn new(c: Quaternion) -> Self {
+        Self { c, iterations: 12, bailout: 4.0 }
+    }
+
+    pub fn with_iterations(mut self, iterations: usize) -> Self {
+        self.iterations = iterations;
+        self
+    }
+
+    /// The distance estimate and the smallest squared length the orbit came to.
+    fn estimate(&self, p: &Vector3) -> (f64, f64) {
+        let mut z = [p.get_x(), p.get_y(), p.get_z(), 0.0];
+        let mut dz = [1.0, 0.0, 0.0, 0.0];
+        let mut trap = f64::INFINITY;
+        for _ in 0..self.iterations {
+            // The derivative of z^2 + c is 2 z dz
+            dz = quaternion_product(&z, &dz).map(|x| 2.0 * x);
+            z = quaternion_product(&z, &z);
+            for (zi, ci) in z.iter_mut().zip(&self.c) {
+                *zi += ci;
+            }
+            let length = quaternion_length(&z);
+            trap = trap.min(length * length);
+            if length > self.bailout {
+                break;
+            }
+        }
+        let r = quaternion_length(&z);
+        (0.5 * r * r.max(1e-12).ln() / quaternion_length(&dz).max(1e-12), trap)
+    }
+}
+
+impl DistanceField for QuaternionJulia {
+    fn distance(&self, p: &Vector3) -> f64 {
+        self.estimate(p).0
+    }
+
+    fn uv(&self, p: &Vector3) -> (f64, f64) {
+        (self.estimate(p).1.clamp(0.0, 1.0), 0.5)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_mandelbulb() {
+        let bulb = Mandelbulb::new(8.0);
+        // The origin is inside, far away points are roughly their distance from the unit ball
+        assert!(bulb.distance(&Vector3::new(0.0, 0.0, 0.0)) <= 1e-6);
+        let far = bulb.distance(&Vector3::new(0.0, 0.0, 5.0));
+        assert!(far > 2.0 && far < 5.0);
+        let (u, _) = bulb.uv(&Vector3::new(0.3, 0.2, 0.1));
+        assert!((0.0..=1.0).contains(&u));
+    }
+
+    #[test]
+    fn test_menger_sponge() {
+        let sponge = MengerSponge::new(3);
+        assert!((sponge.distance(&Vector3::new(3.0, 0.5, 0.5)) - 2.0).abs() < 1e-9);
+        // The center of the cube is punched out by the first iteration, solid corners stay
+        assert!(sponge.distance(&Vector3::new(0.0, 0.0, 0.0)) > 0.0);
+        assert!(sponge.distance(&Vector3::new(0.99, 0.99, 0.99)) < 0.0);
+        assert_eq!(sponge.uv(&Vector3::new(0.0, 0.0, 0.0)).0, 1.0 / 3.0);
+    }
+
+    #[test]
+    fn test_quaternion_julia() {
+        let julia = QuaternionJulia::new([-0.2, 0.6, 0.2, 0.2]);
+        assert!(julia.distance(&Vector3::new(0.0, 0.0, 0.0)) < 0.01);
+        assert!(julia.distance(&Vector3::new(3.0, 0.0, 0.0)) > 1.0);
+    }
+}
@@ -0,0 +1,256 @@
+use crate::basics::{Vector3, Material};
+use crate::raycast::{Ray, Hit};
+use crate::shapes::SceneObject;
+use crate::texture::ImageTexture;
+
+/// Distance to a hit, the grid indices of the triangle's corners and the barycentric weights of the hit.
+type GridHit = (f64, [(usize, usize); 3], (f64, f64, f64));
+
+/// Terrain over a regular grid of height samples. Each grid cell is split into two triangles, and
+/// rays walk the cells they pass over in order instead of testing every triangle.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Heightfield {
+    columns: usize,
+    rows: usize,
+    heights: Vec<f64>,
+    normals: Vec<Vector3>,
+    corner: Vector3,
+    size: Vector3,
+    min_height: f64,
+    max_height: f64,
+    material: Material,
+}
+
+impl Heightfield {
+    /// A grid of `columns` samples along x and `rows` along z, stored row by row starting at the
+    /// corner. The grid is stretched over `size` along x and z, and the heights are multiplied by
+    /// its y, so heights between 0 and 1 fill the whole size.
+    pub fn new<T: Into<Vector3>, U: Into<Vector3>>(columns: usize, rows: usize, heights: Vec<f64>, corner: T, size: U, material: Material) -> Self {
+        assert!(columns >= 2 && rows >= 2, "a heightfield needs at least 2x2 samples");
+        assert_eq!(heights.len(), columns * rows, "sample count doesn't match grid size");
+        let size = size.into();
+        let heights: Vec<f64> = heights.into_iter().map(|h| h * size.get_y()).collect();
+        let min_height = heights.iter().cloned().fold(f64::INFINITY, f64::min);
+        let max_height = heights.iter().cloned().fold(f64::NEG_INFINITY, f64::max);
+
+        let mut heightfield = Self { columns, rows, heights, normals: Vec::new(), corner: corner.into(), size, min_height, max_height, material };
+        heightfield.normals = (0..rows).flat_map(|j| (0..columns).map(move |i| (i, j)))
+            .map(|(i, j)| heightfield.vertex_normal(i, j))
+            .collect();
+        heightfield
+    }
+
+    /// A heightfield with a sample per pixel, using the brightness as height. The image's top row
+    /// ends up at the far end along z.
+    pub fn from_image<T: Into<Vector3>, U: Into<Vector3>>(image: &ImageTexture, corner: T, size: U, material: Material) -> Self {
+        let (width, height) = image.get_size();
+        let heights = (0..height).rev().flat_map(|y| (0..width).map(move |x| (x, y)))
+            .map(|(x, y)| {
+                let col = Vector3::from(image.get_pixel(x, y));
+                (col.get_x() + col.get_y() + col.get_z()) / 3.0
+            })
+            .collect();
+        Self::new(width, height, heights, corner, size, material)
+    }
+
+    fn cell_size(&self) -> (f64, f64) {
+        (self.size.get_x() / (self.columns - 1) as f64, self.size.get_z() / (self.rows - 1) as f64)
+    }
+
+    fn vertex(&self, i: usize, j: usize) -> Vector3 {
+        let (dx, dz) = self.cell_size();
+        self.corner + Vector3::new(i as f64 * dx, self.heights[j * self.columns + i], j as f64 * dz)
+    }
+
+    /// Normal from the slopes to the neighbouring samples.
+    fn vertex_normal(&self, i: usize, j: usize) -> Vector3 {
+        let (dx, dz) = self.cell_size();
+        let height = |i: usize, j: usize| self.heights[j * self.columns + i];
+        let (left, right) = (i.saturating_sub(1), (i + 1).min(self.columns - 1));
+        let (back, front) = (j.saturating_sub(1), (j + 1).min(self.rows - 1));
+        let slope_x = (height(right, j) - height(left, j)) / ((right - left) as f64 * dx);
+        let slope_z = (height(i, front) - height(i, back)) / ((front - back) as f64 * dz);
+        Vector3::new(-slope_x, 1.0, -slope_z).norm()
+    }
+
+    /// Distances where the ray enters and leaves the bounding box, within its interval.
+    fn bounds(&self, ray: &Ray) -> Option<(f64, f64)> {
+        let low = Vector3::new(self.corner.get_x(), self.corner.get_y() + self.min_height, self.corner.get_z());
+        let high = Vector3::new(self.corner.get_x() + self.size.get_x(), self.corner.get_y() + self.max_height, self.corner.get_z() + self.size.get_z());
+        let mut near = ray.get_t_min();
+        let mut far = ray.get_t_max();
+        let axes = [
+            (ray.get_origin().get_x(), ray.get_direction().get_x(), low.get_x(), high.get_x()),
+            (ray.get_origin().get_y(), ray.get_direction().get_y(), low.get_y(), high.get_y()),
+            (ray.get_origin().get_z(), ray.get_direction().get_z(), low.get_z(), high.get_z()),
+        ];
+        for &(origin, direction, low, high) in &axes {
+            if direction.abs() < 1e-12 {
+                if origin < low || origin > high {
+                    return None;
+                }
+                continue;
+            }
+            let (t1, t2) = ((low - origin) / direction, (high - origin) / direction);
+            near = near.max(t1.min(t2));
+            far = far.min(t1.max(t2));
+        }
+        if near <= far {
+            Some((near, far))
+        } else {
+            None
+        }
+    }
+
+    /// The closest hit in a cell, as the distance, the vertex indices and the barycentric weights.
+    fn cell_hit(&self, ray: &Ray, i: usize, j: usize) -> Option<GridHit> {
+        let corners = [(i, j), (i + 1, j), (i + 1, j + 1), (i, j + 1)];
+        let mut closest: Option<GridHit> = None;
+        for triangle in &[[corners[0], corners[2], corners[1]], [corners[0], corners[3], corners[2]]] {
+            let [a, b, c] = triangle.map(|(i, j)| self.vertex(i, j));
+            if let Some((t, u, v)) = intersect_triangle(ray, a, b, c) {
+                if closest.is_none_or(|(closest_t, _, _)| t < closest_t) {
+                    closest = Some((t, *triangle, (1.0 - u - v, u, v)));
+                }
+            }
+        }
+        closest
+    }
+
+    /// Walks the grid cells under the ray from near to far, stopping at the first cell with a hit.
+    fn trace(&self, ray: &Ray) -> Option<GridHit> {
+        let (t_start, t_end) = self.bounds(ray)?;
+        let (dx, dz) = self.cell_size();
+        let start = ray.at(t_start) - self.corner;
+        let direction = ray.get_direction();
+
+        let cell = |x: f64, cell_size: f64, count: usize| ((x / cell_size).floor().max(0.0) as usize).min(count - 2);
+        let mut i = cell(start.get_x(), dx, self.columns);
+        let mut j = cell(start.get_z(), dz, self.rows);
+
+        // Distances to the next cell boundary along each axis, and between boundaries
+        let axis = |origin: f64, direction: f64, index: usize, cell_size: f64| -> (isize, f64, f64) {
+            if direction > 0.0 {
+                (1, t_start + ((index + 1) as f64 * cell_size - origin) / direction, cell_size / direction)
+            } else if direction < 0.0 {
+                (-1, t_start + (index as f64 * cell_size - origin) / direction, -cell_size / direction)
+            } else {
+                (0, f64::INFINITY, f64::INFINITY)
+            }
+        };
+        let (step_i, mut next_i, delta_i) = axis(start.get_x(), direction.get_x(), i, dx);
+        let (step_j, mut next_j, delta_j) = axis(start.get_z(), direction.get_z(), j, dz);
+
+        loop {
+            if let Some(hit) = self.cell_hit(ray, i, j) {
+                return Some(hit);
+            }
+            if next_i.min(next_j) > t_end {
+                return None;
+            }
+            if next_i < next_j {
+                if (step_i < 0 && i == 0) || (step_i > 0 && i + 2 >= self.columns) {
+                    return None;
+                }
+                i = (i as isize + step_i) as usize;
+                next_i += delta_i;
+            } else {
+                if (step_j < 0 && j == 0) || (step_j > 0 && j + 2 >= self.rows) {
+                    return None;
+                }
+                j = (j as isize + step_j) as usize;
+                next_j += delta_j;
+            }
+        }
+    }
+}
+
+/// Distance and barycentric coordinates of the second and third corner where the ray hits the
+/// triangle within its interval.
+fn intersect_triangle(ray: &Ray, a: Vector3, b: Vector3, c: Vector3) -> Option<(f64, f64, f64)> {
+    let (edge1, edge2) = (b - a, c - a);
+    let p = ray.get_direction().cross(edge2);
+    let det = edge1.dot(p);
+    if det.abs() < 1e-12 {
+        return None;
+    }
+    let offset = *ray.get_origin() - a;
+    let u = offset.dot(p) / det;
+    let q = offset.cross(edge1);
+    let v = ray.get_direction().dot(q) / det;
+    let t = edge2.dot(q) / det;
+    if u < 0.0 || v < 0.0 || u + v > 1.0 || !ray.contains(t) {
+        return None;
+    }
+    Some((t, u, v))
+}
+
+impl SceneObject for Heightfield {
+    fn ray_intersects(&self, ray: &Ray) -> Option<Hit<'_>> {
+        let (t, vertices, (w0, w1, w2)) = self.trace(ray)?;
+        let [a, b, c] = vertices.map(|(i, j)| self.vertex(i, j));
+        let normal = |(i, j): (usize, usize)| self.normals[j * self.columns + i];
+        let shading_normal = normal(vertices[0]) * w0 + normal(vertices[1]) * w1 + normal(vertices[2]) * w2;
+
+        let local = ray.at(t) - self.corner;
+        let uv = (local.get_x() / self.size.get_x(), local.get_z() / self.size.get_z());
+        Some(Hit::new(ray, t, (b - a).cross(c - a), &self.material)
+            .with_shading_normal(shading_normal)
+            .with_uv(uv)
+            .with_tangent(Vector3::new(1.0, 0.0, 0.0))
+            .with_bitangent(Vector3::new(0.0, 0.0, 1.0))
+            .with_object_position(local))
+    }
+
+    fn occludes(&self, ray: &Ray) -> bool {
+        self.trace(ray).is_some()
+    }
+
+    fn get_material(&self) -> &Material {
+        &self.material
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::basics::Color;
+
+    fn ridge() -> Heightfield {
+        // Three columns with a peak in the middle one, over a 2x2 square
+        let heights = vec![0.0, 1.0, 0.0, 0.0, 1.0, 0.0, 0.0, 1.0, 0.0];
+        Heightfield::new(3, 3, heights, (0.0, 0.0, 0.0), (2.0, 1.0, 2.0), Material::new((1.0, 1.0, 1.0), 0.0))
+    }
+
+    #[test]
+    fn test_heightfield_intersect() {
+        let ridge = ridge();
+        let top = ridge.ray_intersects(&Ray::new((1.0, 5.0, 1.0), (0.0, -1.0, 0.0))).unwrap();
+        assert!((top.get_t() - 4.0).abs() < 1e-9);
+        // The peak's vertex normal points straight up, while the slopes are at 45 degrees
+        assert!((*top.get_shading_normal() - Vector3::new(0.0, 1.0, 0.0)).mag() < 1e-9);
+        let slope = ridge.ray_intersects(&Ray::new((0.5, 5.0, 1.0), (0.0, -1.0, 0.0))).unwrap();
+        assert!((slope.get_t() - 4.5).abs() < 1e-9);
+        assert!((*slope.get_normal() - Vector3::new(-1.0, 1.0, 0.0).norm()).mag() < 1e-9);
+
+        // A horizontal ray crossing the whole grid hits the far side of the ridge
+        let across = ridge.ray_intersects(&Ray::new((-1.0, 0.5, 0.5), (1.0, 0.0, 0.0))).unwrap();
+        assert!((across.get_t() - 1.5).abs() < 1e-9);
+        assert!(!ridge.occludes(&Ray::new((-1.0, 0.5, 0.5), (1.0, 0.0, 0.0)).with_interval(0.0, 1.0)));
+        assert!(ridge.ray_intersects(&Ray::new((-1.0, 1.5, 0.5), (1.0, 0.0, 0.0))).is_none());
+        // Walking the cells backwards and diagonally
+        let back = ridge.ray_intersects(&Ray::new((3.0, 0.5, 2.5), (-1.0, 0.0, -1.0))).unwrap();
+        assert!((back.get_position().get_x() - 1.5).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_heightfield_from_image() {
+        let black = Color::new(0.0, 0.0, 0.0);
+        let white = Color::new(1.0, 1.0, 1.0);
+        // White top row ends up at the far end
+        let image = ImageTexture::new(2, 2, vec![white, white, black, black]);
+        let slope = Heightfield::from_image(&image, (0.0, 0.0, 0.0), (1.0, 1.0, 1.0), Material::new((1.0, 1.0, 1.0), 0.0));
+        let hit = slope.ray_intersects(&Ray::new((0.5, 5.0, 0.25), (0.0, -1.0, 0.0))).unwrap();
+        assert!((hit.get_position().get_y() - 0.25).abs() < 1e-9);
+    }
+}
@@ -0,0 +1,15 @@
+pub mod basics;
+pub mod shapes;
+pub mod scene;
+pub mod raycast;
+pub mod texture;
+pub mod procedural;
+pub mod mesh;
+pub mod solver;
+pub mod csg;
+pub mod sdf;
+pub mod heightfield;
+pub mod metaballs;
+pub mod fractal;
+pub mod patch;
+pub mod curve;
@@ -1,36 +1,136 @@
-mod basics;
-mod shapes;
-mod scene;
-mod raycast;
-
-use crate::shapes::{Sphere, Light, Plane, Triangle};
-use crate::scene::Scene;
-use crate::basics::Material;
+use raycaster::shapes::{Sphere, Light, Quad, Triangle, Cuboid, Cylinder, Cone, Torus, Quadric, Instance, SceneObject};
+use raycaster::scene::{Scene, Group, Camera, Projection};
+use raycaster::basics::{Vector3, Color, Material, Transform};
+use std::sync::Arc;
+use raycaster::raycast::AmbientOcclusion;
+use raycaster::csg::Csg;
+use raycaster::sdf::{SdfObject, BoxField, SphereField, SmoothUnion, Translated, Twist, Scaled};
+use raycaster::fractal::Mandelbulb;
+use raycaster::texture::{ImageTexture, WrapMode};
+use raycaster::procedural::{Checkerboard, Marble, NoiseTexture, Perlin, Turbulence};
+use raycaster::heightfield::Heightfield;
+use raycaster::metaballs::Metaballs;
+use raycaster::patch::BezierPatch;
+use raycaster::curve::{Curve, CurveMode};
+use raycaster::mesh::{Mesh, MeshObject};
 
 fn main() {
     let mut scene: Scene = Scene::new((0.0, 0.0, 0.0));
+    // Projection::Orthographic(20.0) would show the back wall head on, without the side walls
+    scene.set_camera(Camera::new((0.0, 0.0, 0.0)).with_projection(Projection::Perspective));
 
     //Spheres
     scene.add(Box::new(Sphere::new(2.0, (0.0, -8.0, 13.0), Material::new((1.0, 0.0, 0.0), 0.3))));
-    scene.add(Box::new(Sphere::new(3.0, (-7.0, -7.0, 17.0), Material::new((0.2, 0.6, 0.9), 0.0))));
+    scene.add(Box::new(Sphere::new(3.0, (-7.0, -7.0, 17.0), Material::new((0.2, 0.6, 0.9), 0.0).with_bump_map(NoiseTexture::new((0.0, 0.0, 0.0), (1.0, 1.0, 1.0), 3.0), 0.05))));
+
+    //Tori
+    scene.add(Box::new(Torus::new((-3.5, -9.0, 9.0), (0.3, 1.0, -0.4), 1.5, 0.5, Material::new((0.8, 0.8, 0.2), 0.4))));
+
+    //Ellipsoids sharing one sphere
+    let ellipsoid: Arc<dyn SceneObject + Send + Sync> = Arc::new(Sphere::new(1.0, (0.0, 0.0, 0.0), Material::new((0.7, 0.3, 0.8), 0.2)));
+    let mut ellipsoids = Group::new("ellipsoids").with_transform(Transform::translate((0.0, 5.0, 15.0)));
+    for &(x, angle) in &[(-6.0, 0.6), (6.0, -0.6)] {
+        let transform = Transform::scale((2.0, 0.8, 0.8))
+            .then(&Transform::rotate((0.0, 0.0, 1.0), angle))
+            .then(&Transform::translate((x, 0.0, 0.0)));
+        ellipsoids.add(Box::new(Instance::new(ellipsoid.clone(), transform)));
+    }
+    scene.add_group(ellipsoids);
+
+    //Boxes
+    let cube = Cuboid::new((3.0, -10.0, 13.0), (7.0, -7.0, 17.0), Material::new((0.9, 0.6, 0.2), 0.0)).rotated((0.0, 1.0, 0.0), 0.5);
+    let hollow = Sphere::new(1.8, (5.0, -8.5, 15.0), Material::new((0.3, 0.3, 0.8), 0.0));
+    scene.add(Box::new(Csg::difference(Box::new(cube), Box::new(hollow))));
+
+    //Distance fields
+    let twisted = Twist::new(BoxField::new((0.8, 1.5, 0.8)).with_rounding(0.15), 0.8);
+    let blob = SmoothUnion::new(twisted, Translated::new(SphereField::new(0.9), (0.0, 1.8, 0.0)), 0.4);
+    scene.add(Box::new(SdfObject::new(blob, (3.0, -8.5, 9.5), 3.0, Material::new((0.3, 0.8, 0.5), 0.1)).with_step_scale(0.6)));
+
+    //Terrain
+    let perlin = Perlin::new(7);
+    let heights = (0..32).flat_map(|j| (0..64).map(move |i| (i, j)))
+        .map(|(i, j)| perlin.turbulence(&Vector3::new(i as f64 * 0.12, 0.0, j as f64 * 0.12), 4).min(1.0))
+        .collect();
+    scene.add(Box::new(Heightfield::new(64, 32, heights, (-4.0, -10.0, 16.0), (8.0, 2.0, 3.9), Material::new((0.5, 0.7, 0.3), 0.0))));
+
+    //Fractals, colored by orbit trap
+    let palette = vec![Color::new(0.1, 0.2, 0.6), Color::new(0.3, 0.3, 0.8), Color::new(0.9, 0.2, 0.1), Color::new(1.0, 0.6, 0.1), Color::new(1.0, 0.95, 0.5)];
+    let palette = ImageTexture::new(palette.len(), 1, palette).with_wrap(WrapMode::Clamp);
+    let bulb = SdfObject::new(Scaled::new(Mandelbulb::new(8.0), 1.8), (4.5, -1.5, 16.0), 2.3, Material::textured(palette, 0.0));
+    scene.add(Box::new(bulb.with_epsilon(5e-4).with_max_steps(128)));
+
+    //Metaballs
+    let blobs = Metaballs::new(0.3, Material::new((0.2, 0.4, 0.9), 0.4))
+        .with_point((-2.0, -2.5, 15.0), 1.6, 1.0)
+        .with_point((-0.6, -2.0, 15.5), 1.4, 1.0)
+        .with_point((0.4, -3.2, 14.5), 1.2, 1.0)
+        .with_segment((1.0, -1.5, 15.0), (2.8, -0.5, 15.5), 0.9, 1.0);
+    scene.add(Box::new(blobs));
+
+    //Quadrics
+    let hourglass = [1.0, -0.3, 1.0, 0.0, 0.0, 0.0, -15.0, -4.2, -22.0, 162.05];
+    scene.add(Box::new(Quadric::new(hourglass, Material::new((0.9, 0.4, 0.6), 0.3)).with_clip_box((5.5, -10.0, 9.0), (9.5, -5.0, 13.0))));
+
+    //Patches
+    let mut sheet = [[Vector3::new(0.0, 0.0, 0.0); 4]; 4];
+    for (i, row) in sheet.iter_mut().enumerate() {
+        for (j, point) in row.iter_mut().enumerate() {
+            let depth = if (i + j) % 2 == 0 { -1.2 } else { 1.2 };
+            *point = Vector3::new(3.0 + 4.0 * j as f64 / 3.0, 2.0 + i as f64, 14.0 + depth);
+        }
+    }
+    scene.add(Box::new(BezierPatch::new(sheet, Material::new((0.9, 0.6, 0.2), 0.2))));
+
+    //Grass
+    for blade in 0..24 {
+        let angle = blade as f64 * 2.4;
+        let (spread, lean) = (0.15 + 0.03 * (blade % 7) as f64, 0.25 + 0.05 * (blade % 5) as f64);
+        let root = Vector3::new(-1.4 + spread * angle.cos(), -10.0, 11.0 + spread * angle.sin());
+        let bend = Vector3::new(lean * angle.cos(), 0.0, lean * angle.sin());
+        let curve = (0..5usize).fold(Curve::new(CurveMode::Ribbon, Material::new((0.3, 0.7, 0.2), 0.0).with_hair_shading(30.0)), |curve, i| {
+            let height = i.saturating_sub(1) as f64 / 3.0;
+            curve.with_point(root + bend * height * height + Vector3::new(0.0, 0.9 * height, 0.0), 0.06 * (1.0 - height) + 0.005)
+        });
+        scene.add(Box::new(curve));
+    }
+
+    //Displaced rock
+    let octahedron = Mesh::parse_obj("v 1 0 0\nv -1 0 0\nv 0 1 0\nv 0 -1 0\nv 0 0 1\nv 0 0 -1\n\
+        f 1 3 5\nf 3 2 5\nf 2 4 5\nf 4 1 5\nf 3 1 6\nf 2 3 6\nf 4 2 6\nf 1 4 6").unwrap();
+    let rock = octahedron.displaced(&Turbulence::new((0.0, 0.0, 0.0), (1.0, 1.0, 1.0), 1.5, 4), 0.6, 4);
+    let rock = Instance::new(Arc::new(MeshObject::new(&rock, Material::new((0.6, 0.55, 0.5), 0.0))), Transform::scale((1.2, 0.9, 1.2)).then(&Transform::translate((-6.3, -9.2, 12.0))));
+    scene.add(Box::new(rock));
+
+    //Subdivision surface, a cube cage with its top edges creased
+    let cage = Mesh::parse_obj("v -1 -1 -1\nv 1 -1 -1\nv 1 1 -1\nv -1 1 -1\nv -1 -1 1\nv 1 -1 1\nv 1 1 1\nv -1 1 1\n\
+        f 1 4 3 2\nf 5 6 7 8\nf 1 2 6 5\nf 2 3 7 6\nf 3 4 8 7\nf 4 1 5 8\nt crease 5/1 2 3 7 6 2 3").unwrap();
+    let pebble = MeshObject::new(&cage.subdivided(3), Material::new((0.8, 0.3, 0.2), 0.1));
+    scene.add(Box::new(Instance::new(Arc::new(pebble), Transform::rotate((0.0, 1.0, 0.0), 0.5).then(&Transform::translate((-6.0, -1.5, 13.0))))));
+
+    //Columns
+    scene.add(Box::new(Cylinder::new((-6.0, -10.0, 19.0), (-6.0, 10.0, 19.0), 0.8, Material::new((0.9, 0.9, 0.9), 0.0))));
+    scene.add(Box::new(Cone::new((6.0, -10.0, 19.0), (6.0, -4.0, 19.0), 1.5, 0.5, Material::new((0.9, 0.9, 0.9), 0.0))));
 
     //Triangles
     scene.add(Box::new(Triangle::new((-2.0, 6.0, 8.0), (2.0, 6.0, 8.0), (0.0, 3.0, 8.0), Material::new((1.0, 0.0, 0.0), 0.0))));
 
     //Left and right walls
-    scene.add(Box::new(Plane::new((-10.0, 0.0, 0.0), (-10.0, 1.0, 0.0), (-10.0, 0.0, 1.0), Material::new((1.0, 1.0, 0.0), 0.2))));
-    scene.add(Box::new(Plane::new((10.0, 0.0, 0.0), (10.0, 0.0, 1.0), (10.0, 1.0, 0.0), Material::new((0.0, 1.0, 0.0), 0.2))));
+    scene.add(Box::new(Quad::new((-10.0, -10.0, -0.1), (0.0, 20.0, 0.0), (0.0, 0.0, 20.1), Material::new((1.0, 1.0, 0.0), 0.2))));
+    scene.add(Box::new(Quad::new((10.0, -10.0, -0.1), (0.0, 0.0, 20.1), (0.0, 20.0, 0.0), Material::new((0.0, 1.0, 0.0), 0.2))));
 
     //Top and bottom walls
-    scene.add(Box::new(Plane::new((0.0, 10.0, 0.0), (1.0, 10.0, 0.0), (0.0, 10.0, 1.0), Material::new((0.0, 0.0, 1.0), 0.0))));
-    scene.add(Box::new(Plane::new((0.0, -10.0, 0.0), (0.0, -10.0, 1.0), (1.0, -10.0, 0.0), Material::new((1.0, 1.0, 1.0), 0.0))));
+    scene.add(Box::new(Quad::new((-10.0, 10.0, -0.1), (20.0, 0.0, 0.0), (0.0, 0.0, 20.1), Material::new((0.0, 0.0, 1.0), 0.0))));
+    scene.add(Box::new(Quad::new((-10.0, -10.0, -0.1), (0.0, 0.0, 20.1), (20.0, 0.0, 0.0), Material::textured(Checkerboard::new((1.0, 1.0, 1.0), (0.3, 0.3, 0.3), 2.0), 0.0))));
 
     //Front and back walls
-    scene.add(Box::new(Plane::new((-1.0, -1.0, 20.0), (-1.0, 0.0, 20.0), (0.0, -1.0, 20.0), Material::new((0.9, 0.0, 1.0), 0.2))));
-    scene.add(Box::new(Plane::new((-1.0, -1.0, -0.1), (1.0, -1.0, -0.1), (-1.0, 1.0, -0.1), Material::new((0.2, 0.5, 0.5), 0.1))));
+    scene.add(Box::new(Quad::new((-10.0, -10.0, 20.0), (0.0, 20.0, 0.0), (20.0, 0.0, 0.0), Material::textured(Marble::new((0.9, 0.85, 0.8), (0.3, 0.2, 0.4), 0.3), 0.2))));
+    scene.add(Box::new(Quad::new((-10.0, -10.0, -0.1), (20.0, 0.0, 0.0), (0.0, 20.0, 0.0), Material::new((0.2, 0.5, 0.5), 0.1))));
 
     //scene.add_light(Light::new((0.0, 4.5, 9.0), (1.0, 1.0, 1.0), 0.3));
-    scene.add_light(Light::new((0.0, 4.5, 7.0), (1.0, 1.0, 1.0), 0.3));
+    scene.add_light(Light::new((0.0, 4.5, 7.0), (0.3, 0.3, 0.3), 0.3));
+
+    scene.set_ambient_occlusion(Some(AmbientOcclusion::new(4.0, 4)));
 
     Scene::render(scene);
 }
\ No newline at end of file
@@ -0,0 +1,655 @@
+use crate::basics::{Vector3, Material};
+use crate::raycast::{Ray, Hit};
+use crate::shapes::{SceneObject, Triangle};
+use crate::texture::{Texture, TextureCoords};
+use std::collections::HashMap;
+use std::io::{Error, ErrorKind};
+use std::path::Path;
+
+/// Highest subdivision level `Mesh::displaced` accepts, which cuts each triangle into about a
+/// million pieces.
+pub const MAX_DISPLACEMENT_LEVEL: u32 = 10;
+
+/// One corner of a face, as indices into the mesh's attribute lists.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct FaceVertex {
+    position: usize,
+    uv: Option<usize>,
+    normal: Option<usize>,
+}
+
+impl FaceVertex {
+    pub fn new(position: usize, uv: Option<usize>, normal: Option<usize>) -> Self {
+        Self { position, uv, normal }
+    }
+}
+
+/// A polygon mesh with shared vertex attributes. Faces can have any number of corners and are
+/// triangulated as fans when the mesh is turned into triangles.
+#[derive(Clone, PartialEq, Debug, Default)]
+pub struct Mesh {
+    positions: Vec<Vector3>,
+    uvs: Vec<(f64, f64)>,
+    normals: Vec<Vector3>,
+    faces: Vec<Vec<FaceVertex>>,
+    /// Crease sharpness of edges, keyed by their position indices in increasing order.
+    creases: HashMap<(usize, usize), f64>,
+}
+
+/// Key of the edge between two positions, whichever way round it's walked.
+fn edge_key(a: usize, b: usize) -> (usize, usize) {
+    (a.min(b), a.max(b))
+}
+
+impl Mesh {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Loads the geometry of a Wavefront OBJ file. Materials, groups and other statements are ignored.
+    pub fn load_obj<P: AsRef<Path>>(path: P) -> std::io::Result<Self> {
+        Self::parse_obj(&std::fs::read_to_string(path)?)
+    }
+
+    /// Parses OBJ source. Crease tags are read for subdivision, see `add_crease`.
+    pub fn parse_obj(source: &str) -> std::io::Result<Self> {
+        let mut mesh = Self::new();
+
+        for (line_number, line) in source.lines().enumerate() {
+            let invalid = |msg: &str| Error::new(ErrorKind::InvalidData, format!("bad obj on line {}: {}", line_number + 1, msg));
+            let mut parts = line.split_whitespace();
+            let numbers = |parts: std::str::SplitWhitespace, count: usize| -> std::io::Result<Vec<f64>> {
+                let values: Vec<f64> = parts.take(count).map(|p| p.parse::<f64>()).collect::<Result<_, _>>()
+                    .map_err(|_| invalid("expected a number"))?;
+                if values.len() < count {
+                    return Err(invalid("too few values"));
+                }
+                Ok(values)
+            };
+
+            match parts.next() {
+                Some("v") => {
+                    let v = numbers(parts, 3)?;
+                    mesh.positions.push(Vector3::new(v[0], v[1], v[2]));
+                }
+                Some("vt") => {
+                    // The v coordinate is optional and defaults to 0
+                    let given = parts.clone().count().clamp(1, 2);
+                    let v = numbers(parts, given)?;
+                    mesh.uvs.push((v[0], v.get(1).copied().unwrap_or(0.0)));
+                }
+                Some("vn") => {
+                    let v = numbers(parts, 3)?;
+                    let normal = Vector3::new(v[0], v[1], v[2]);
+                    if normal.mag() == 0.0 {
+                        return Err(invalid("zero length normal"));
+                    }
+                    mesh.normals.push(normal.norm());
+                }
+                Some("f") => {
+                    // Indices are 1-based, negative ones count back from the latest element
+                    let resolve = |index: &str, count: usize| -> std::io::Result<usize> {
+                        let i: isize = index.parse().map_err(|_| invalid("bad index"))?;
+                        let resolved = if i < 0 { count as isize + i } else { i - 1 };
+                        if resolved < 0 || resolved >= count as isize {
+                            return Err(invalid("index out of range"));
+                        }
+                        Ok(resolved as usize)
+                    };
+
+                    let mut face = Vec::new();
+                    for corner in parts {
+                        let mut indices = corner.split('/');
+                        let position = resolve(indices.next().unwrap_or(""), mesh.positions.len())?;
+                        let uv = match indices.next() {
+                            Some(i) if !i.is_empty() => Some(resolve(i, mesh.uvs.len())?),
+                            _ => None,
+                        };
+                        let normal = match indices.next() {
+                            Some(i) if !i.is_empty() => Some(resolve(i, mesh.normals.len())?),
+                            _ => None,
+                        };
+                        face.push(FaceVertex { position, uv, normal });
+                    }
+                    if face.len() < 3 {
+                        return Err(invalid("face with fewer than three corners"));
+                    }
+                    mesh.faces.push(face);
+                }
+                Some("t") => {
+                    // Subdivision tags, written `t crease 2/1 a b sharpness` with 0-based position
+                    // indices, or with more indices for a chain of edges. Other tags are ignored
+                    if parts.next() != Some("crease") {
+                        continue;
+                    }
+                    let counts: Vec<usize> = parts.next().unwrap_or("").split('/').map(|n| n.parse()).collect::<Result<_, _>>()
+                        .map_err(|_| invalid("bad tag argument counts"))?;
+                    if counts.len() < 2 || counts[0] < 2 || counts[1] != 1 {
+                        return Err(invalid("a crease needs at least two positions and one sharpness"));
+                    }
+                    let indices: Vec<usize> = parts.by_ref().take(counts[0]).map(|i| i.parse()).collect::<Result<_, _>>()
+                        .map_err(|_| invalid("bad index"))?;
+                    let sharpness = numbers(parts, 1)?[0];
+                    if indices.len() < counts[0] {
+                        return Err(invalid("too few values"));
+                    }
+                    if indices.iter().any(|&i| i >= mesh.positions.len()) {
+                        return Err(invalid("index out of range"));
+                    }
+                    if sharpness.is_nan() || sharpness < 0.0 {
+                        return Err(invalid("negative crease sharpness"));
+                    }
+                    for edge in indices.windows(2) {
+                        mesh.add_crease(edge[0], edge[1], sharpness);
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        Ok(mesh)
+    }
+
+    /// Adds a vertex position and returns its index.
+    pub fn add_position<T: Into<Vector3>>(&mut self, position: T) -> usize {
+        self.positions.push(position.into());
+        self.positions.len() - 1
+    }
+
+    /// Adds texture coordinates and returns their index.
+    pub fn add_uv(&mut self, uv: (f64, f64)) -> usize {
+        self.uvs.push(uv);
+        self.uvs.len() - 1
+    }
+
+    /// Adds a normal and returns its index.
+    pub fn add_normal<T: Into<Vector3>>(&mut self, normal: T) -> usize {
+        self.normals.push(normal.into().norm());
+        self.normals.len() - 1
+    }
+
+    /// Adds a face with corners in counterclockwise order. Panics if a corner refers to an
+    /// attribute that doesn't exist.
+    pub fn add_face(&mut self, corners: Vec<FaceVertex>) {
+        assert!(corners.len() >= 3, "face with fewer than three corners");
+        for corner in &corners {
+            assert!(corner.position < self.positions.len(), "position index out of range");
+            assert!(corner.uv.is_none_or(|uv| uv < self.uvs.len()), "uv index out of range");
+            assert!(corner.normal.is_none_or(|normal| normal < self.normals.len()), "normal index out of range");
+        }
+        self.faces.push(corners);
+    }
+
+    /// Marks the edge between two positions, given by their 0-based indices into `get_positions`,
+    /// as a crease for subdivision. It stays sharp for `sharpness` levels, with fractions blending
+    /// towards smooth. Panics if either position doesn't exist.
+    pub fn add_crease(&mut self, a: usize, b: usize, sharpness: f64) {
+        assert!(a < self.positions.len() && b < self.positions.len(), "crease position index out of range");
+        self.creases.insert(edge_key(a, b), sharpness);
+    }
+
+    pub fn get_positions(&self) -> &Vec<Vector3> {
+        &self.positions
+    }
+
+    pub fn get_faces(&self) -> &Vec<Vec<FaceVertex>> {
+        &self.faces
+    }
+
+    /// Replaces all vertex normals with smooth ones, averaged from the faces around each vertex
+    /// and weighted by face area.
+    pub fn compute_smooth_normals(&mut self) {
+        let mut normals = vec![Vector3::new(0.0, 0.0, 0.0); self.positions.len()];
+        for face in &self.faces {
+            let first = self.positions[face[0].position];
+            for i in 1..face.len() - 1 {
+                let area_normal = (self.positions[face[i].position] - first).cross(self.positions[face[i + 1].position] - first);
+                for corner in &[face[0], face[i], face[i + 1]] {
+                    normals[corner.position] += area_normal;
+                }
+            }
+        }
+
+        self.normals = normals.into_iter().map(|n| if n.mag() > 0.0 { n.norm() } else { n }).collect();
+        for face in &mut self.faces {
+            for corner in face.iter_mut() {
+                corner.normal = Some(corner.position);
+            }
+        }
+    }
+
+    /// A copy with every triangle cut into `2^level` pieces along each edge and every vertex moved
+    /// along its normal by the brightness of `height` there, times `scale`. Faces without normals
+    /// use smooth ones, and vertices shared by several faces move along their normals averaged.
+    /// Texture coordinates are kept on faces whose corners all have them. The result gets new
+    /// smooth normals to match its shape. `level` can be at most `MAX_DISPLACEMENT_LEVEL`.
+    pub fn displaced<T: Texture>(&self, height: &T, scale: f64, level: u32) -> Mesh {
+        assert!(level <= MAX_DISPLACEMENT_LEVEL, "displacement level above MAX_DISPLACEMENT_LEVEL");
+        let mut source = self.clone();
+        if self.faces.iter().flatten().any(|corner| corner.normal.is_none()) {
+            source.compute_smooth_normals();
+        }
+
+        let steps = 1usize << level;
+        let mut mesh = Mesh::new();
+        // Vertices shared by neighbouring triangles are keyed by the original positions they're
+        // blended from and their weights. Each face adds its normal and height to the vertex's
+        // sums, and the vertices are only moved at the end, so no cracks open and the face order
+        // doesn't matter
+        let mut shared: HashMap<Vec<(usize, usize)>, usize> = HashMap::new();
+        let mut displacements: Vec<(Vector3, f64, usize)> = Vec::new();
+        for face in &source.faces {
+            for i in 1..face.len() - 1 {
+                let corners = [face[0], face[i], face[i + 1]];
+                // Texture coordinates are only carried over when every corner has them
+                let has_uvs = corners.iter().all(|c| c.uv.is_some());
+                let uv = |c: usize| if has_uvs { source.uvs[corners[c].uv.unwrap()] } else { (0.0, 0.0) };
+                let normal = |c: usize| source.normals[corners[c].normal.unwrap()];
+
+                // Grid vertices by row, with `steps + 1 - row` in each
+                let mut grid: Vec<Vec<FaceVertex>> = Vec::new();
+                for row in 0..=steps {
+                    let mut vertices = Vec::new();
+                    for column in 0..=steps - row {
+                        let weights = [steps - row - column, column, row];
+                        let blend = |f: &dyn Fn(usize) -> Vector3| {
+                            (0..3).fold(Vector3::new(0.0, 0.0, 0.0), |sum, c| sum + f(c) * (weights[c] as f64 / steps as f64))
+                        };
+                        let position = blend(&|c| source.positions[corners[c].position]);
+                        let point_normal = blend(&normal).norm();
+                        let point_uv = (0..3).fold((0.0, 0.0), |(u, v), c| {
+                            let w = weights[c] as f64 / steps as f64;
+                            (u + uv(c).0 * w, v + uv(c).1 * w)
+                        });
+
+                        let mut key: Vec<(usize, usize)> = (0..3).filter(|&c| weights[c] > 0).map(|c| (corners[c].position, weights[c])).collect();
+                        key.sort_unstable();
+                        let index = *shared.entry(key).or_insert_with(|| {
+                            displacements.push((Vector3::new(0.0, 0.0, 0.0), 0.0, 0));
+                            mesh.add_position(position)
+                        });
+                        let color = Vector3::from(height.color_at(&TextureCoords::new(point_uv, position, position)));
+                        let displacement = &mut displacements[index];
+                        displacement.0 += point_normal;
+                        displacement.1 += (color.get_x() + color.get_y() + color.get_z()) / 3.0 * scale;
+                        displacement.2 += 1;
+                        let uv_index = if has_uvs { Some(mesh.add_uv(point_uv)) } else { None };
+                        vertices.push(FaceVertex { position: index, uv: uv_index, normal: None });
+                    }
+                    grid.push(vertices);
+                }
+
+                for row in 0..steps {
+                    for column in 0..steps - row {
+                        mesh.faces.push(vec![grid[row][column], grid[row][column + 1], grid[row + 1][column]]);
+                        if column + 1 < steps - row {
+                            mesh.faces.push(vec![grid[row][column + 1], grid[row + 1][column + 1], grid[row + 1][column]]);
+                        }
+                    }
+                }
+            }
+        }
+        for (position, (normal, amount, count)) in mesh.positions.iter_mut().zip(displacements) {
+            if normal.mag() > 1e-12 {
+                *position += normal.norm() * (amount / count as f64);
+            }
+        }
+        mesh.compute_smooth_normals();
+        mesh
+    }
+
+    /// A copy refined by `levels` steps of Catmull-Clark subdivision, after which every face is a
+    /// quad. Texture coordinates are interpolated linearly and the result gets smooth normals.
+    pub fn subdivided(&self, levels: u32) -> Mesh {
+        let mut mesh = self.clone();
+        for _ in 0..levels {
+            mesh = mesh.subdivide_once();
+        }
+        if levels > 0 {
+            mesh.compute_smooth_normals();
+        }
+        mesh
+    }
+
+    /// One step of Catmull-Clark. The new positions are the moved old ones, then one per edge,
+    /// then one per face. Boundary edges are treated as infinitely sharp creases.
+    fn subdivide_once(&self) -> Mesh {
+        let face_points: Vec<Vector3> = self.faces.iter()
+            .map(|face| face.iter().fold(Vector3::new(0.0, 0.0, 0.0), |sum, c| sum + self.positions[c.position]) / face.len() as f64)
+            .collect();
+
+        // Edges in the order they're first met, with the faces on each side
+        let mut edges: Vec<(usize, usize)> = Vec::new();
+        let mut edge_faces: HashMap<(usize, usize), Vec<usize>> = HashMap::new();
+        let mut vertex_faces: Vec<Vec<usize>> = vec![Vec::new(); self.positions.len()];
+        for (f, face) in self.faces.iter().enumerate() {
+            for (i, corner) in face.iter().enumerate() {
+                let key = edge_key(corner.position, face[(i + 1) % face.len()].position);
+                let faces = edge_faces.entry(key).or_insert_with(|| {
+                    edges.push(key);
+                    Vec::new()
+                });
+                faces.push(f);
+                vertex_faces[corner.position].push(f);
+            }
+        }
+        let sharpness = |key: &(usize, usize)| {
+            if edge_faces[key].len() != 2 { f64::INFINITY } else { self.creases.get(key).copied().unwrap_or(0.0) }
+        };
+        let mut vertex_edges: Vec<Vec<(usize, usize)>> = vec![Vec::new(); self.positions.len()];
+        for key in &edges {
+            vertex_edges[key.0].push(*key);
+            vertex_edges[key.1].push(*key);
+        }
+
+        let mut mesh = Mesh::new();
+        for (v, &point) in self.positions.iter().enumerate() {
+            let around = &vertex_edges[v];
+            if around.is_empty() {
+                mesh.positions.push(point);
+                continue;
+            }
+            let other = |key: &(usize, usize)| self.positions[if key.0 == v { key.1 } else { key.0 }];
+            let valence = around.len() as f64;
+            let faces_average = vertex_faces[v].iter().fold(Vector3::new(0.0, 0.0, 0.0), |sum, &f| sum + face_points[f]) / vertex_faces[v].len() as f64;
+            let edges_average = around.iter().fold(Vector3::new(0.0, 0.0, 0.0), |sum, key| sum + (point + other(key)) / 2.0) / valence;
+            let smooth = (faces_average + edges_average * 2.0 + point * (valence - 3.0)) / valence;
+
+            let sharp: Vec<&(usize, usize)> = around.iter().filter(|key| sharpness(key) > 0.0).collect();
+            let blend = |sharp_point: Vector3| {
+                let average = sharp.iter().map(|key| sharpness(key).min(1.0)).sum::<f64>() / sharp.len() as f64;
+                smooth * (1.0 - average) + sharp_point * average
+            };
+            mesh.positions.push(match sharp.len() {
+                0 | 1 => smooth,
+                2 => blend((other(sharp[0]) + point * 6.0 + other(sharp[1])) / 8.0),
+                _ => blend(point),
+            });
+        }
+
+        let mut edge_points: HashMap<(usize, usize), usize> = HashMap::new();
+        for key in &edges {
+            let middle = (self.positions[key.0] + self.positions[key.1]) / 2.0;
+            let faces = &edge_faces[key];
+            let point = if faces.len() == 2 {
+                let smooth = (middle * 2.0 + face_points[faces[0]] + face_points[faces[1]]) / 4.0;
+                let amount = sharpness(key).min(1.0);
+                smooth * (1.0 - amount) + middle * amount
+            } else {
+                middle
+            };
+            edge_points.insert(*key, mesh.add_position(point));
+
+            // Creases carry on into both halves, one level less sharp
+            if let Some(&crease) = self.creases.get(key) {
+                if crease > 1.0 {
+                    mesh.add_crease(key.0, edge_points[key], crease - 1.0);
+                    mesh.add_crease(edge_points[key], key.1, crease - 1.0);
+                }
+            }
+        }
+
+        for (f, face) in self.faces.iter().enumerate() {
+            let center = mesh.add_position(face_points[f]);
+            let count = face.len();
+            let uvs: Option<Vec<(f64, f64)>> = face.iter().map(|c| c.uv.map(|uv| self.uvs[uv])).collect();
+            let new_uvs = &mut mesh.uvs;
+            let mut uv = |weights: &[(usize, f64)]| {
+                uvs.as_ref().map(|uvs| {
+                    let (u, v) = weights.iter().fold((0.0, 0.0), |(u, v), &(i, w)| (u + uvs[i].0 * w, v + uvs[i].1 * w));
+                    new_uvs.push((u, v));
+                    new_uvs.len() - 1
+                })
+            };
+            let center_uv = uv(&(0..count).map(|i| (i, 1.0 / count as f64)).collect::<Vec<_>>());
+
+            for i in 0..count {
+                let (previous, next) = ((i + count - 1) % count, (i + 1) % count);
+                let position = |j: usize| face[j].position;
+                let corner = FaceVertex { position: position(i), uv: uv(&[(i, 1.0)]), normal: None };
+                let after = FaceVertex { position: edge_points[&edge_key(position(i), position(next))], uv: uv(&[(i, 0.5), (next, 0.5)]), normal: None };
+                let before = FaceVertex { position: edge_points[&edge_key(position(previous), position(i))], uv: uv(&[(previous, 0.5), (i, 0.5)]), normal: None };
+                mesh.faces.push(vec![corner, after, FaceVertex { position: center, uv: center_uv, normal: None }, before]);
+            }
+        }
+        mesh
+    }
+
+    /// Splits every face into triangles. Corners that all have normals are smooth shaded, and
+    /// corners that all have texture coordinates keep them.
+    pub fn to_triangles(&self, material: &Material) -> Vec<Triangle> {
+        let mut triangles = Vec::new();
+        for face in &self.faces {
+            for i in 1..face.len() - 1 {
+                let corners = [face[0], face[i], face[i + 1]];
+                let p = |c: usize| self.positions[corners[c].position];
+                let mut triangle = Triangle::new(p(0), p(1), p(2), material.clone());
+
+                if let [Some(a), Some(b), Some(c)] = [corners[0].uv, corners[1].uv, corners[2].uv] {
+                    triangle = triangle.with_uvs(self.uvs[a], self.uvs[b], self.uvs[c]);
+                }
+                if let [Some(a), Some(b), Some(c)] = [corners[0].normal, corners[1].normal, corners[2].normal] {
+                    triangle = triangle.with_normals(self.normals[a], self.normals[b], self.normals[c]);
+                }
+
+                triangles.push(triangle);
+            }
+        }
+        triangles
+    }
+}
+
+/// The triangles of a mesh as a single scene object, so one copy can be shared between instances.
+/// Rays missing the mesh's bounding sphere skip all of its triangles.
+pub struct MeshObject {
+    triangles: Vec<Triangle>,
+    center: Vector3,
+    radius: f64,
+    material: Material,
+}
+
+impl MeshObject {
+    pub fn new(mesh: &Mesh, material: Material) -> Self {
+        let positions = &mesh.positions;
+        let center = if positions.is_empty() {
+            Vector3::new(0.0, 0.0, 0.0)
+        } else {
+            positions.iter().fold(Vector3::new(0.0, 0.0, 0.0), |sum, p| sum + *p) / positions.len() as f64
+        };
+        let radius = positions.iter().map(|p| (*p - center).mag()).fold(0.0, f64::max);
+        Self { triangles: mesh.to_triangles(&material), center, radius, material }
+    }
+
+    pub fn get_triangles(&self) -> &Vec<Triangle> {
+        &self.triangles
+    }
+
+    fn in_bounds(&self, ray: &Ray) -> bool {
+        let offset = *ray.get_origin() - self.center;
+        let b = ray.get_direction().dot(offset);
+        let disc = b * b - (offset.dot(offset) - self.radius * self.radius);
+        disc >= 0.0 && -b - disc.sqrt() <= ray.get_t_max() && -b + disc.sqrt() >= ray.get_t_min()
+    }
+}
+
+impl SceneObject for MeshObject {
+    fn ray_intersects(&self, ray: &Ray) -> Option<Hit<'_>> {
+        if !self.in_bounds(ray) {
+            return None;
+        }
+        let mut closest = None;
+        let mut ray = *ray;
+        for triangle in &self.triangles {
+            if let Some(hit) = triangle.ray_intersects(&ray) {
+                ray = ray.with_interval(ray.get_t_min(), hit.get_t());
+                closest = Some(hit);
+            }
+        }
+        closest
+    }
+
+    fn occludes(&self, ray: &Ray) -> bool {
+        self.in_bounds(ray) && self.triangles.iter().any(|triangle| triangle.occludes(ray))
+    }
+
+    fn get_material(&self) -> &Material {
+        &self.material
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::procedural::Gradient;
+
+    const QUAD: &str = "
+# unit quad in the xy plane
+v 0 0 0
+v 1 0 0
+v 1 1 0
+v 0 1 0
+vt 0 0
+vt 1 0
+vt 1 1
+vt 0 1
+vn 0 0 1
+f 1/1/1 2/2/1 3/3/1 -1/4/1
+";
+
+    #[test]
+    fn test_parse_obj() {
+        let mesh = Mesh::parse_obj(QUAD).unwrap();
+        assert_eq!(mesh.positions.len(), 4);
+        assert_eq!(mesh.faces.len(), 1);
+        assert_eq!(mesh.faces[0][3], FaceVertex { position: 3, uv: Some(3), normal: Some(0) });
+        assert_eq!(mesh.to_triangles(&Material::new((1.0, 1.0, 1.0), 0.0)).len(), 2);
+
+        assert!(Mesh::parse_obj("v 0 0 0\nf 1 2 3").is_err());
+        assert_eq!(Mesh::parse_obj("vt 0.5").unwrap().uvs, vec![(0.5, 0.0)]);
+        assert!(Mesh::parse_obj("vt").is_err());
+        assert!(Mesh::parse_obj("vn 0 0 0").is_err());
+    }
+
+    #[test]
+    fn test_parse_creases() {
+        let creased = Mesh::parse_obj(&(CUBE.to_string() + "t crease 3/1 2 3 7 1.5\nt corner 1/1 0 2.0\n")).unwrap();
+        assert_eq!(creased.creases.len(), 2);
+        assert_eq!(creased.creases[&(2, 3)], 1.5);
+        assert_eq!(creased.creases[&(3, 7)], 1.5);
+
+        assert!(Mesh::parse_obj(&(CUBE.to_string() + "t crease 2/1 0 8 1.0\n")).is_err());
+        assert!(Mesh::parse_obj(&(CUBE.to_string() + "t crease 2/1 0 1\n")).is_err());
+        assert!(Mesh::parse_obj(&(CUBE.to_string() + "t crease 1/1 0 1.0\n")).is_err());
+        assert!(Mesh::parse_obj(&(CUBE.to_string() + "t crease 2/1 0 1 -1.0\n")).is_err());
+    }
+
+    #[test]
+    #[should_panic(expected = "crease position index out of range")]
+    fn test_crease_out_of_range() {
+        Mesh::parse_obj(CUBE).unwrap().add_crease(7, 8, 1.0);
+    }
+
+    #[test]
+    fn test_mesh_object() {
+        let object = MeshObject::new(&Mesh::parse_obj(QUAD).unwrap(), Material::new((1.0, 1.0, 1.0), 0.0));
+        assert_eq!(object.get_triangles().len(), 2);
+        let hit = object.ray_intersects(&Ray::new((0.75, 0.25, -5.0), (0.0, 0.0, 1.0))).unwrap();
+        assert!((hit.get_t() - 5.0).abs() < 1e-9);
+        assert!(object.ray_intersects(&Ray::new((3.0, 0.5, -5.0), (0.0, 0.0, 1.0))).is_none());
+        assert!(!object.occludes(&Ray::new((0.5, 0.5, -5.0), (0.0, 0.0, 1.0)).with_interval(0.0, 4.0)));
+    }
+
+    #[test]
+    fn test_displaced() {
+        let mesh = Mesh::parse_obj(QUAD).unwrap();
+        let flat = mesh.displaced(&Gradient::new((0.0, 0.0, 0.0), (0.0, 0.0, 0.0), (0.0, 0.0, 0.0), (1.0, 0.0, 0.0), 1.0), 1.0, 2);
+        // Each of the two triangles splits into 16, sharing the 25 grid points
+        assert_eq!(flat.faces.len(), 32);
+        assert_eq!(flat.positions.len(), 25);
+        assert!(flat.positions.iter().all(|p| p.get_z() == 0.0));
+
+        let raised = mesh.displaced(&Gradient::new((0.5, 0.5, 0.5), (0.5, 0.5, 0.5), (0.0, 0.0, 0.0), (1.0, 0.0, 0.0), 1.0), 0.4, 1);
+        assert!(raised.positions.iter().all(|p| (p.get_z() - 0.2).abs() < 1e-9));
+        let object = MeshObject::new(&raised, Material::new((1.0, 1.0, 1.0), 0.0));
+        let hit = object.ray_intersects(&Ray::new((0.3, 0.6, -5.0), (0.0, 0.0, 1.0))).unwrap();
+        assert!((hit.get_t() - 5.2).abs() < 1e-9);
+        assert!((hit.get_uv().0 - 0.3).abs() < 1e-9 && (hit.get_uv().1 - 0.6).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_displaced_shared_normals() {
+        // The two faces have their own normals, so the corners they share move along both
+        let positions = "v 0 0 0\nv 1 0 0\nv 0 1 0\nv 1 1 0\nvn 0 0 1\nvn 1 0 0\n";
+        let (first, second) = ("f 1//1 2//1 3//1\n", "f 2//2 4//2 3//2\n");
+        let gray = Gradient::new((0.5, 0.5, 0.5), (0.5, 0.5, 0.5), (0.0, 0.0, 0.0), (1.0, 0.0, 0.0), 1.0);
+        let forward = Mesh::parse_obj(&(positions.to_string() + first + second)).unwrap().displaced(&gray, 1.0, 0);
+        let backward = Mesh::parse_obj(&(positions.to_string() + second + first)).unwrap().displaced(&gray, 1.0, 0);
+
+        let moved = Vector3::new(1.0, 0.0, 0.0) + Vector3::new(1.0, 0.0, 1.0).norm() * 0.5;
+        for mesh in [&forward, &backward].iter() {
+            assert_eq!(mesh.positions.len(), 4);
+            assert!(mesh.positions.iter().any(|p| (*p - moved).mag() < 1e-9));
+        }
+    }
+
+    #[test]
+    fn test_displaced_mixed_uvs() {
+        // Only the first corner has texture coordinates, so the face gets none
+        let mesh = Mesh::parse_obj("v 0 0 0\nv 1 0 0\nv 0 1 0\nvt 1 1\nf 1/1 2 3\n").unwrap();
+        let gray = Gradient::new((0.5, 0.5, 0.5), (0.5, 0.5, 0.5), (0.0, 0.0, 0.0), (1.0, 0.0, 0.0), 1.0);
+        let displaced = mesh.displaced(&gray, 1.0, 2);
+        assert!(displaced.uvs.is_empty());
+        assert!(displaced.faces.iter().flatten().all(|corner| corner.uv.is_none()));
+    }
+
+    const CUBE: &str = "
+v -1 -1 -1
+v 1 -1 -1
+v 1 1 -1
+v -1 1 -1
+v -1 -1 1
+v 1 -1 1
+v 1 1 1
+v -1 1 1
+f 1 4 3 2
+f 5 6 7 8
+f 1 2 6 5
+f 2 3 7 6
+f 3 4 8 7
+f 4 1 5 8
+";
+
+    #[test]
+    fn test_subdivided() {
+        let cube = Mesh::parse_obj(CUBE).unwrap();
+        let smooth = cube.subdivided(1);
+        assert_eq!(smooth.faces.len(), 24);
+        assert_eq!(smooth.positions.len(), 26);
+        // Corners move a third of the way past the face centers' average towards the middle
+        assert!((smooth.positions[6] - Vector3::new(5.0, 5.0, 5.0) / 9.0).mag() < 1e-9);
+        assert!(smooth.faces.iter().all(|face| face.len() == 4 && face.iter().all(|c| c.normal.is_some())));
+
+        // With every edge creased the corners stay and edges split at their middles
+        let mut creased = cube.clone();
+        for face in cube.faces.iter() {
+            for i in 0..4 {
+                creased.add_crease(face[i].position, face[(i + 1) % 4].position, 2.0);
+            }
+        }
+        let sharp = creased.subdivided(1);
+        assert_eq!(sharp.positions[6], Vector3::new(1.0, 1.0, 1.0));
+        assert!(sharp.positions[8..20].iter().all(|p| [p.get_x(), p.get_y(), p.get_z()].iter().filter(|x| x.abs() == 1.0).count() == 2));
+        // A second level is still sharp, a third level isn't
+        assert_eq!(creased.subdivided(2).positions[6], Vector3::new(1.0, 1.0, 1.0));
+        assert!(creased.subdivided(3).positions[6].get_x() < 1.0);
+    }
+
+    #[test]
+    fn test_smooth_normals() {
+        let mut mesh = Mesh::parse_obj("v 0 0 0\nv 1 0 0\nv 0 1 0\nv 0 0 1\nf 1 2 3\nf 1 4 2").unwrap();
+        mesh.compute_smooth_normals();
+        // The shared edge averages the two faces' normals
+        assert!((mesh.normals[1] - Vector3::new(0.0, 1.0, 1.0).norm()).mag() < 1e-9);
+        assert!((mesh.normals[2] - Vector3::new(0.0, 0.0, 1.0)).mag() < 1e-9);
+    }
+}
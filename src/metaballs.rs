@@ -0,0 +1,230 @@
+use crate::basics::{Vector3, Material};
+use crate::raycast::{Ray, Hit};
+use crate::shapes::SceneObject;
+
+/// Steepest slope of the falloff `(1 - x^2)^2` over `0 <= x <= 1`, reached at `x = 1 / sqrt(3)`.
+const MAX_FALLOFF_SLOPE: f64 = 1.5396;
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+enum BlobShape {
+    Point(Vector3),
+    Segment(Vector3, Vector3),
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+struct Blob {
+    shape: BlobShape,
+    radius: f64,
+    weight: f64,
+}
+
+impl Blob {
+    /// Offset from the closest point of the source to `p`.
+    fn offset(&self, p: &Vector3) -> Vector3 {
+        match self.shape {
+            BlobShape::Point(center) => *p - center,
+            BlobShape::Segment(start, end) => {
+                let segment = end - start;
+                let along = ((*p - start).dot(segment) / segment.dot(segment).max(1e-12)).clamp(0.0, 1.0);
+                *p - (start + segment * along)
+            }
+        }
+    }
+
+    /// A sphere containing the whole area of influence.
+    fn bounding_sphere(&self) -> (Vector3, f64) {
+        match self.shape {
+            BlobShape::Point(center) => (center, self.radius),
+            BlobShape::Segment(start, end) => ((start + end) / 2.0, self.radius + (end - start).mag() / 2.0),
+        }
+    }
+}
+
+/// A stretch of a ray within reach of some sources, with the steepest slope their summed field can
+/// have and the smallest of their radii.
+#[derive(Copy, Clone, PartialEq, Debug)]
+struct Reach {
+    near: f64,
+    far: f64,
+    slope: f64,
+    min_radius: f64,
+}
+
+/// A blobby surface around point and line segment sources. Each source's field falls off
+/// smoothly to zero at its radius, and the surface lies where the summed field reaches the
+/// threshold, so sources close to each other melt together.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Metaballs {
+    blobs: Vec<Blob>,
+    threshold: f64,
+    material: Material,
+}
+
+impl Metaballs {
+    pub fn new(threshold: f64, material: Material) -> Self {
+        Self { blobs: Vec::new(), threshold, material }
+    }
+
+    /// Adds a source around a point. A negative weight carves into the other sources.
+    pub fn with_point<T: Into<Vector3>>(mut self, center: T, radius: f64, weight: f64) -> Self {
+        self.blobs.push(Blob { shape: BlobShape::Point(center.into()), radius, weight });
+        self
+    }
+
+    /// Adds a source around the segment between two points.
+    pub fn with_segment<T: Into<Vector3>, U: Into<Vector3>>(mut self, start: T, end: U, radius: f64, weight: f64) -> Self {
+        self.blobs.push(Blob { shape: BlobShape::Segment(start.into(), end.into()), radius, weight });
+        self
+    }
+
+    /// The summed field at `p` and its gradient.
+    fn field(&self, p: &Vector3) -> (f64, Vector3) {
+        let mut value = 0.0;
+        let mut gradient = Vector3::new(0.0, 0.0, 0.0);
+        for blob in &self.blobs {
+            let offset = blob.offset(p);
+            let x2 = offset.dot(offset) / (blob.radius * blob.radius);
+            if x2 < 1.0 {
+                let falloff = 1.0 - x2;
+                value += blob.weight * falloff * falloff;
+                gradient += offset * (-4.0 * blob.weight * falloff / (blob.radius * blob.radius));
+            }
+        }
+        (value, gradient)
+    }
+
+    /// Parts of the ray's interval within reach of any source, sorted and merged.
+    fn spans(&self, ray: &Ray) -> Vec<Reach> {
+        let mut spans: Vec<Reach> = self.blobs.iter().filter_map(|blob| {
+            let (center, radius) = blob.bounding_sphere();
+            let offset = *ray.get_origin() - center;
+            let b = ray.get_direction().dot(offset);
+            let disc = b * b - (offset.dot(offset) - radius * radius);
+            if disc < 0.0 {
+                return None;
+            }
+            let (near, far) = ((-b - disc.sqrt()).max(ray.get_t_min()), (-b + disc.sqrt()).min(ray.get_t_max()));
+            if near <= far {
+                Some(Reach { near, far, slope: blob.weight.abs() * MAX_FALLOFF_SLOPE / blob.radius, min_radius: blob.radius })
+            } else {
+                None
+            }
+        }).collect();
+        spans.sort_by(|a, b| a.near.partial_cmp(&b.near).unwrap());
+
+        let mut merged: Vec<Reach> = Vec::new();
+        for span in spans {
+            match merged.last_mut() {
+                Some(last) if span.near <= last.far => {
+                    last.far = last.far.max(span.far);
+                    last.slope += span.slope;
+                    last.min_radius = last.min_radius.min(span.min_radius);
+                }
+                _ => merged.push(span),
+            }
+        }
+        merged
+    }
+
+    /// Steps along each span by how far the field is from the threshold divided by the steepest
+    /// slope the sources reaching the span can give it, which can't step over the surface, then
+    /// bisects the crossing.
+    fn distance(&self, ray: &Ray) -> Option<f64> {
+        let level = |t: f64| self.field(&ray.at(t)).0 - self.threshold;
+
+        for span in self.spans(ray) {
+            if span.slope == 0.0 {
+                continue;
+            }
+            // Close to the surface the steps shrink, so they're kept to a fraction of the smallest source
+            let min_step = span.min_radius * 1e-3;
+            let mut t = span.near;
+            let mut value = level(t);
+            let inside = value > 0.0;
+            while t < span.far {
+                if value.abs() < 1e-12 {
+                    return Some(t);
+                }
+                let next = (t + (value.abs() / span.slope).max(min_step)).min(span.far);
+                let next_value = level(next);
+                if (next_value > 0.0) != inside {
+                    // Crossed the surface between t and next
+                    let (mut low, mut high) = (t, next);
+                    for _ in 0..50 {
+                        let middle = (low + high) / 2.0;
+                        if (level(middle) > 0.0) == inside {
+                            low = middle;
+                        } else {
+                            high = middle;
+                        }
+                    }
+                    return Some((low + high) / 2.0);
+                }
+                t = next;
+                value = next_value;
+            }
+        }
+        None
+    }
+}
+
+impl SceneObject for Metaballs {
+    fn ray_intersects(&self, ray: &Ray) -> Option<Hit<'_>> {
+        let t = self.distance(ray)?;
+        // The field grows towards the sources, so the outward normal is against the gradient
+        let (_, gradient) = self.field(&ray.at(t));
+        Some(Hit::new(ray, t, -gradient, &self.material))
+    }
+
+    fn occludes(&self, ray: &Ray) -> bool {
+        self.distance(ray).is_some()
+    }
+
+    fn get_material(&self) -> &Material {
+        &self.material
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_metaball_intersect() {
+        // With a threshold of 1/4 a lone source's surface is at 1/sqrt(2) of its radius
+        let single = Metaballs::new(0.25, Material::new((1.0, 0.0, 0.0), 0.0)).with_point((0.0, 0.0, 10.0), 2.0, 1.0);
+        let hit = single.ray_intersects(&Ray::new((0.0, 0.0, 0.0), (0.0, 0.0, 1.0))).unwrap();
+        assert!((hit.get_t() - (10.0 - 2.0f64.sqrt())).abs() < 1e-6);
+        assert!((*hit.get_normal() - Vector3::new(0.0, 0.0, -1.0)).mag() < 1e-6);
+        assert!(single.ray_intersects(&Ray::new((0.0, 1.5, 0.0), (0.0, 0.0, 1.0))).is_none());
+        // Leaving from the inside
+        let inside = single.ray_intersects(&Ray::new((0.0, 0.0, 10.0), (1.0, 0.0, 0.0))).unwrap();
+        assert!((inside.get_t() - 2.0f64.sqrt()).abs() < 1e-6);
+        assert!(!inside.is_front_face());
+
+        let segment = Metaballs::new(0.25, Material::new((1.0, 0.0, 0.0), 0.0)).with_segment((-2.0, 0.0, 10.0), (2.0, 0.0, 10.0), 1.0, 1.0);
+        let top = segment.ray_intersects(&Ray::new((1.0, 5.0, 10.0), (0.0, -1.0, 0.0))).unwrap();
+        assert!((top.get_t() - (5.0 - 0.5f64.sqrt())).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_metaballs_merge() {
+        let material = Material::new((1.0, 0.0, 0.0), 0.0);
+        let pair = Metaballs::new(0.25, material.clone()).with_point((-1.2, 0.0, 10.0), 2.0, 1.0).with_point((1.2, 0.0, 10.0), 2.0, 1.0);
+        // Separately neither reaches the midpoint, together they bridge the gap
+        assert!(pair.ray_intersects(&Ray::new((0.0, 5.0, 10.0), (0.0, -1.0, 0.0))).is_some());
+        let apart = Metaballs::new(0.25, material).with_point((-3.0, 0.0, 10.0), 2.0, 1.0).with_point((3.0, 0.0, 10.0), 2.0, 1.0);
+        assert!(apart.ray_intersects(&Ray::new((0.0, 5.0, 10.0), (0.0, -1.0, 0.0))).is_none());
+    }
+
+    #[test]
+    fn test_many_small_sources() {
+        // Tiny sources off to the side have steep fields, but shouldn't slow down the march elsewhere
+        let mut blobs = Metaballs::new(0.25, Material::new((1.0, 0.0, 0.0), 0.0)).with_point((0.0, 0.0, 10.0), 2.0, 1.0);
+        for i in 0..500 {
+            blobs = blobs.with_point((50.0, i as f64 * 0.01, 10.0), 0.001, 1.0);
+        }
+        let hit = blobs.ray_intersects(&Ray::new((0.0, 0.0, 0.0), (0.0, 0.0, 1.0))).unwrap();
+        assert!((hit.get_t() - (10.0 - 2.0f64.sqrt())).abs() < 1e-6);
+    }
+}
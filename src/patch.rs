@@ -0,0 +1,288 @@
+use crate::basics::{Vector3, Material};
+use crate::mesh::{Mesh, MeshObject, FaceVertex};
+use crate::raycast::{Ray, Hit};
+use crate::shapes::SceneObject;
+use std::io::{Error, ErrorKind};
+use std::path::Path;
+
+/// Finest grid a patch is split into along each direction.
+const MAX_DIVISIONS: usize = 64;
+
+/// Bernstein basis of degree 3 at `t` and its derivative.
+fn bernstein(t: f64) -> ([f64; 4], [f64; 4]) {
+    let s = 1.0 - t;
+    (
+        [s * s * s, 3.0 * t * s * s, 3.0 * t * t * s, t * t * t],
+        [-3.0 * s * s, 3.0 * s * s - 6.0 * t * s, 6.0 * t * s - 3.0 * t * t, 3.0 * t * t],
+    )
+}
+
+/// A bicubic Bezier patch, optionally rational with a weight per control point, which makes it a
+/// single span of a cubic NURBS surface. It's tessellated into smooth shaded triangles when it's
+/// created, taking the normals and uvs of the vertices from the exact surface.
+pub struct BezierPatch {
+    control_points: [[Vector3; 4]; 4],
+    weights: [[f64; 4]; 4],
+    tolerance: f64,
+    mesh: MeshObject,
+}
+
+impl BezierPatch {
+    /// Control points are given in rows along v, each row going along u. The default tolerance
+    /// is a hundredth of the control points' extent.
+    pub fn new(control_points: [[Vector3; 4]; 4], material: Material) -> Self {
+        Self::rational(control_points, [[1.0; 4]; 4], material)
+    }
+
+    /// A patch pulled towards control points with larger weights. Weights must be positive.
+    pub fn rational(control_points: [[Vector3; 4]; 4], weights: [[f64; 4]; 4], material: Material) -> Self {
+        let points = control_points.iter().flatten();
+        let extent = points.clone().flat_map(|a| points.clone().map(move |b| (*a - *b).mag())).fold(0.0, f64::max);
+        let mut patch = Self { control_points, weights, tolerance: extent * 1e-2, mesh: MeshObject::new(&Mesh::new(), material.clone()) };
+        patch.mesh = MeshObject::new(&patch.tessellate(), material);
+        patch
+    }
+
+    /// Sets how far the triangles may stray from the surface, and tessellates again.
+    pub fn with_tolerance(mut self, tolerance: f64) -> Self {
+        self.tolerance = tolerance;
+        let material = self.mesh.get_material().clone();
+        self.mesh = MeshObject::new(&self.tessellate(), material);
+        self
+    }
+
+    pub fn get_triangle_count(&self) -> usize {
+        self.mesh.get_triangles().len()
+    }
+
+    /// The surface point at `(u, v)` and its partial derivatives along u and v.
+    fn evaluate(&self, u: f64, v: f64) -> (Vector3, Vector3, Vector3) {
+        let (bu, du) = bernstein(u);
+        let (bv, dv) = bernstein(v);
+        // Sums of the weighted points and the weights, and their derivatives
+        let zero = Vector3::new(0.0, 0.0, 0.0);
+        let (mut point, mut point_u, mut point_v) = (zero, zero, zero);
+        let (mut weight, mut weight_u, mut weight_v) = (0.0, 0.0, 0.0);
+        for i in 0..4 {
+            for j in 0..4 {
+                let w = self.weights[i][j];
+                let p = self.control_points[i][j] * w;
+                point += p * (bv[i] * bu[j]);
+                point_u += p * (bv[i] * du[j]);
+                point_v += p * (dv[i] * bu[j]);
+                weight += w * bv[i] * bu[j];
+                weight_u += w * bv[i] * du[j];
+                weight_v += w * dv[i] * bu[j];
+            }
+        }
+        let surface = point / weight;
+        (surface, (point_u - surface * weight_u) / weight, (point_v - surface * weight_v) / weight)
+    }
+
+    pub fn point(&self, u: f64, v: f64) -> Vector3 {
+        self.evaluate(u, v).0
+    }
+
+    /// The unit normal at `(u, v)`, along the u derivative crossed with the v derivative.
+    pub fn normal(&self, u: f64, v: f64) -> Vector3 {
+        let (_, along_u, along_v) = self.evaluate(u, v);
+        let normal = along_u.cross(along_v);
+        if normal.mag() > 1e-12 {
+            return normal.norm();
+        }
+        // Edges collapsed to a point, like the top of the teapot's lid, have no normal of their
+        // own, so use one from just inside the patch
+        let inside = |t: f64| t.clamp(1e-4, 1.0 - 1e-4);
+        let (_, along_u, along_v) = self.evaluate(inside(u), inside(v));
+        along_u.cross(along_v).norm()
+    }
+
+    /// Grid size needed along u and v. Linear interpolation over steps of `hu` and `hv` is off by
+    /// at most `(hu^2 Duu + 2 hu hv Duv + hv^2 Dvv) / 8`, with the second derivatives bounded by
+    /// six times the control net's largest second differences and nine times its largest twist.
+    /// That bound only holds for polynomial patches, so rational patches with uneven weights use
+    /// the finest grid.
+    fn divisions(&self) -> (usize, usize) {
+        let w = self.weights[0][0];
+        if self.weights.iter().flatten().any(|&weight| weight != w) {
+            return (MAX_DIVISIONS, MAX_DIVISIONS);
+        }
+        let p = &self.control_points;
+        let second_difference = |a: &Vector3, b: &Vector3, c: &Vector3| (*c - *b * 2.0 + *a).mag();
+        let curvature_u = p.iter().flat_map(|row| row.windows(3))
+            .map(|w| second_difference(&w[0], &w[1], &w[2]))
+            .fold(0.0, f64::max);
+        let curvature_v = p.windows(3).flat_map(|rows| rows[0].iter().zip(&rows[1]).zip(&rows[2]))
+            .map(|((a, b), c)| second_difference(a, b, c))
+            .fold(0.0, f64::max);
+        let twist = p.windows(2).flat_map(|rows| rows[0].windows(2).zip(rows[1].windows(2)))
+            .map(|(near, far)| (far[1] - far[0] - near[1] + near[0]).mag())
+            .fold(0.0, f64::max);
+        // Bounding 2 hu hv Duv by (hu^2 + hv^2) Duv lets each direction be sized on its own
+        let count = |curvature: f64| {
+            if self.tolerance <= 0.0 {
+                return MAX_DIVISIONS;
+            }
+            let bound = 6.0 * curvature + 9.0 * twist;
+            ((bound / (4.0 * self.tolerance)).sqrt().ceil() as usize).clamp(1, MAX_DIVISIONS)
+        };
+        (count(curvature_u), count(curvature_v))
+    }
+
+    /// The patch as a grid of quads, sized to stay within the tolerance of the surface.
+    pub fn tessellate(&self) -> Mesh {
+        let (columns, rows) = self.divisions();
+        let mut mesh = Mesh::new();
+        for row in 0..=rows {
+            for column in 0..=columns {
+                let (u, v) = (column as f64 / columns as f64, row as f64 / rows as f64);
+                let index = mesh.add_position(self.point(u, v));
+                mesh.add_uv((u, v));
+                mesh.add_normal(self.normal(u, v));
+                debug_assert_eq!(index, row * (columns + 1) + column);
+            }
+        }
+
+        let corner = |column: usize, row: usize| {
+            let index = row * (columns + 1) + column;
+            FaceVertex::new(index, Some(index), Some(index))
+        };
+        for row in 0..rows {
+            for column in 0..columns {
+                mesh.add_face(vec![corner(column, row), corner(column + 1, row), corner(column + 1, row + 1), corner(column, row + 1)]);
+            }
+        }
+        mesh
+    }
+
+    /// Loads a file of bicubic patches in the text format the Utah teapot is usually shared in.
+    pub fn load_bpt<P: AsRef<Path>>(path: P, material: &Material) -> std::io::Result<Vec<Self>> {
+        Self::parse_bpt(&std::fs::read_to_string(path)?, material)
+    }
+
+    /// Parses the patch count, then for each patch its degrees along u and v (which must both be 3)
+    /// and its 16 control points, one per line.
+    pub fn parse_bpt(source: &str, material: &Material) -> std::io::Result<Vec<Self>> {
+        let invalid = |msg: &str| Error::new(ErrorKind::InvalidData, format!("bad patch file: {}", msg));
+        let mut numbers = source.split_whitespace().map(|n| n.parse::<f64>().map_err(|_| invalid("expected a number")));
+        let mut next = || numbers.next().unwrap_or_else(|| Err(invalid("unexpected end of file")));
+
+        let count = next()?;
+        if !count.is_finite() || count < 0.0 || count.fract() != 0.0 {
+            return Err(invalid("bad patch count"));
+        }
+        let count = count as usize;
+        let mut patches = Vec::with_capacity(count.min(source.len() / 50));
+        for _ in 0..count {
+            if next()? != 3.0 || next()? != 3.0 {
+                return Err(invalid("only bicubic patches are supported"));
+            }
+            let mut control_points = [[Vector3::new(0.0, 0.0, 0.0); 4]; 4];
+            for row in control_points.iter_mut() {
+                for point in row.iter_mut() {
+                    *point = Vector3::new(next()?, next()?, next()?);
+                }
+            }
+            patches.push(Self::new(control_points, material.clone()));
+        }
+        Ok(patches)
+    }
+}
+
+impl SceneObject for BezierPatch {
+    fn ray_intersects(&self, ray: &Ray) -> Option<Hit<'_>> {
+        self.mesh.ray_intersects(ray)
+    }
+
+    fn occludes(&self, ray: &Ray) -> bool {
+        self.mesh.occludes(ray)
+    }
+
+    fn get_material(&self) -> &Material {
+        self.mesh.get_material()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// A patch over the unit square at z = 5, bulging towards -z by `bulge` in the middle.
+    fn control_points(bulge: f64) -> [[Vector3; 4]; 4] {
+        let mut points = [[Vector3::new(0.0, 0.0, 0.0); 4]; 4];
+        for (i, row) in points.iter_mut().enumerate() {
+            for (j, point) in row.iter_mut().enumerate() {
+                let inner = (1..3).contains(&i) && (1..3).contains(&j);
+                *point = Vector3::new(j as f64 / 3.0, i as f64 / 3.0, if inner { 5.0 - bulge } else { 5.0 });
+            }
+        }
+        points
+    }
+
+    #[test]
+    fn test_flat_patch() {
+        let patch = BezierPatch::new(control_points(0.0), Material::new((1.0, 1.0, 1.0), 0.0));
+        assert_eq!(patch.get_triangle_count(), 2);
+        let hit = patch.ray_intersects(&Ray::new((0.25, 0.75, 0.0), (0.0, 0.0, 1.0))).unwrap();
+        assert!((hit.get_t() - 5.0).abs() < 1e-9);
+        assert!((hit.get_uv().0 - 0.25).abs() < 1e-9 && (hit.get_uv().1 - 0.75).abs() < 1e-9);
+        assert!((*hit.get_shading_normal() - Vector3::new(0.0, 0.0, -1.0)).mag() < 1e-9);
+    }
+
+    #[test]
+    fn test_curved_patch() {
+        let patch = BezierPatch::new(control_points(1.0), Material::new((1.0, 1.0, 1.0), 0.0)).with_tolerance(1e-3);
+        assert!(patch.get_triangle_count() > 100);
+        // The middle sits at 9/16 of the inner control points' offset
+        let middle = patch.point(0.5, 0.5);
+        assert!((middle.get_z() - (5.0 - 9.0 / 16.0)).abs() < 1e-9);
+        assert!((patch.normal(0.5, 0.5) - Vector3::new(0.0, 0.0, 1.0)).mag() < 1e-9);
+
+        // x and y follow u and v exactly since the control points are evenly spaced
+        let hit = patch.ray_intersects(&Ray::new((0.4, 0.45, 0.0), (0.0, 0.0, 1.0))).unwrap();
+        assert!((hit.get_t() - patch.point(0.4, 0.45).get_z()).abs() < 1e-3);
+        assert!((*hit.get_shading_normal() + patch.normal(0.4, 0.45)).mag() < 1e-2);
+    }
+
+    #[test]
+    fn test_rational_patch() {
+        let material = Material::new((1.0, 1.0, 1.0), 0.0);
+        let mut weights = [[1.0; 4]; 4];
+        weights[1][1] = 4.0;
+        let plain = BezierPatch::new(control_points(1.0), material.clone());
+        let pulled = BezierPatch::rational(control_points(1.0), weights, material);
+        // The heavier control point pulls the surface closer to it
+        let target = Vector3::new(1.0 / 3.0, 1.0 / 3.0, 4.0);
+        let (u, v) = (0.4, 0.4);
+        assert!((pulled.point(u, v) - target).mag() < (plain.point(u, v) - target).mag());
+        // The polynomial bound doesn't cover uneven weights
+        assert_eq!(pulled.divisions(), (MAX_DIVISIONS, MAX_DIVISIONS));
+        assert!(plain.divisions().0 < MAX_DIVISIONS);
+    }
+
+    #[test]
+    fn test_parse_bpt() {
+        let mut source = String::from("1\n3 3\n");
+        for row in control_points(0.5).iter() {
+            for p in row {
+                source += &format!("{} {} {}\n", p.get_x(), p.get_y(), p.get_z());
+            }
+        }
+        let material = Material::new((1.0, 1.0, 1.0), 0.0);
+        let patches = BezierPatch::parse_bpt(&source, &material).unwrap();
+        assert_eq!(patches.len(), 1);
+        assert_eq!(patches[0].control_points, control_points(0.5));
+        assert!(BezierPatch::parse_bpt("1\n2 3\n", &material).is_err());
+        assert!(BezierPatch::parse_bpt("2\n3 3\n0 0 0", &material).is_err());
+    }
+
+    #[test]
+    fn test_parse_bpt_count() {
+        let material = Material::new((1.0, 1.0, 1.0), 0.0);
+        let error = BezierPatch::parse_bpt("1e30\n3 3\n", &material).err().unwrap();
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+        assert!(BezierPatch::parse_bpt("0.5\n", &material).is_err());
+        assert!(BezierPatch::parse_bpt("-1\n", &material).is_err());
+        assert!(BezierPatch::parse_bpt("0\n", &material).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,438 @@
+use crate::basics::{Vector3, Color};
+use crate::texture::{Texture, TextureCoords};
+
+/// Which hit position a procedural texture is evaluated at.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum TextureSpace {
+    /// Fixed in the world, objects moving through it show different parts of the pattern
+    World,
+    /// Relative to the object's origin, the pattern sticks to the object
+    Object,
+}
+
+impl TextureSpace {
+    fn point(&self, coords: &TextureCoords) -> Vector3 {
+        match self {
+            TextureSpace::World => *coords.get_position(),
+            TextureSpace::Object => *coords.get_object_position(),
+        }
+    }
+}
+
+fn mix(a: Color, b: Color, t: f64) -> Color {
+    let t = t.clamp(0.0, 1.0);
+    (Vector3::from(a) * (1.0 - t) + Vector3::from(b) * t).into()
+}
+
+/// Improved Perlin gradient noise over 3D space.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Perlin {
+    permutation: Vec<usize>,
+}
+
+impl Perlin {
+    pub fn new(seed: u64) -> Self {
+        let mut permutation: Vec<usize> = (0..256).collect();
+        // xorshift is plenty for shuffling the table
+        let mut state = seed.wrapping_mul(0x9E37_79B9_7F4A_7C15) | 1;
+        for i in (1..256).rev() {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            permutation.swap(i, (state % (i as u64 + 1)) as usize);
+        }
+        permutation.extend_from_within(..);
+        Self { permutation }
+    }
+
+    fn fade(t: f64) -> f64 {
+        t * t * t * (t * (t * 6.0 - 15.0) + 10.0)
+    }
+
+    fn lerp(t: f64, a: f64, b: f64) -> f64 {
+        a + t * (b - a)
+    }
+
+    fn grad(hash: usize, x: f64, y: f64, z: f64) -> f64 {
+        let h = hash & 15;
+        let u = if h < 8 { x } else { y };
+        let v = if h < 4 { y } else if h == 12 || h == 14 { x } else { z };
+        (if h & 1 == 0 { u } else { -u }) + (if h & 2 == 0 { v } else { -v })
+    }
+
+    /// Noise value at `point`, roughly in [-1, 1].
+    pub fn noise(&self, point: &Vector3) -> f64 {
+        let (fx, fy, fz) = (point.get_x().floor(), point.get_y().floor(), point.get_z().floor());
+        let xi = (fx as i64 & 255) as usize;
+        let yi = (fy as i64 & 255) as usize;
+        let zi = (fz as i64 & 255) as usize;
+        let (x, y, z) = (point.get_x() - fx, point.get_y() - fy, point.get_z() - fz);
+        let (u, v, w) = (Self::fade(x), Self::fade(y), Self::fade(z));
+
+        let p = &self.permutation;
+        let a = p[xi] + yi;
+        let aa = p[a] + zi;
+        let ab = p[a + 1] + zi;
+        let b = p[xi + 1] + yi;
+        let ba = p[b] + zi;
+        let bb = p[b + 1] + zi;
+
+        Self::lerp(w,
+            Self::lerp(v,
+                Self::lerp(u, Self::grad(p[aa], x, y, z), Self::grad(p[ba], x - 1.0, y, z)),
+                Self::lerp(u, Self::grad(p[ab], x, y - 1.0, z), Self::grad(p[bb], x - 1.0, y - 1.0, z))),
+            Self::lerp(v,
+                Self::lerp(u, Self::grad(p[aa + 1], x, y, z - 1.0), Self::grad(p[ba + 1], x - 1.0, y, z - 1.0)),
+                Self::lerp(u, Self::grad(p[ab + 1], x, y - 1.0, z - 1.0), Self::grad(p[bb + 1], x - 1.0, y - 1.0, z - 1.0))))
+    }
+
+    /// Sum of `octaves` layers of absolute noise, each at double the frequency and half the amplitude.
+    pub fn turbulence(&self, point: &Vector3, octaves: usize) -> f64 {
+        let mut sum = 0.0;
+        let mut p = *point;
+        let mut amplitude = 1.0;
+        for _ in 0..octaves {
+            sum += self.noise(&p).abs() * amplitude;
+            p *= 2.0;
+            amplitude *= 0.5;
+        }
+        sum
+    }
+}
+
+/// Alternating cubes of two colors.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Checkerboard {
+    even: Color,
+    odd: Color,
+    size: f64,
+    space: TextureSpace,
+}
+
+impl Checkerboard {
+    pub fn new<T: Into<Color>, U: Into<Color>>(even: T, odd: U, size: f64) -> Self {
+        Self { even: even.into(), odd: odd.into(), size, space: TextureSpace::World }
+    }
+
+    pub fn in_space(mut self, space: TextureSpace) -> Self {
+        self.space = space;
+        self
+    }
+}
+
+impl Texture for Checkerboard {
+    fn color_at(&self, coords: &TextureCoords) -> Color {
+        // Nudged so that faces lying exactly on a cell border don't flicker between colors
+        let p = self.space.point(coords) / self.size + Vector3::new(1e-6, 1e-6, 1e-6);
+        let sum = p.get_x().floor() + p.get_y().floor() + p.get_z().floor();
+        if sum.rem_euclid(2.0) < 1.0 {
+            self.even
+        } else {
+            self.odd
+        }
+    }
+}
+
+/// Smooth Perlin noise blending between two colors, with `octaves` > 1 giving fractal noise.
+#[derive(Clone, PartialEq, Debug)]
+pub struct NoiseTexture {
+    noise: Perlin,
+    low: Color,
+    high: Color,
+    scale: f64,
+    octaves: usize,
+    space: TextureSpace,
+}
+
+impl NoiseTexture {
+    pub fn new<T: Into<Color>, U: Into<Color>>(low: T, high: U, scale: f64) -> Self {
+        Self { noise: Perlin::new(0), low: low.into(), high: high.into(), scale, octaves: 1, space: TextureSpace::World }
+    }
+
+    pub fn with_octaves(mut self, octaves: usize) -> Self {
+        self.octaves = octaves;
+        self
+    }
+
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.noise = Perlin::new(seed);
+        self
+    }
+
+    pub fn in_space(mut self, space: TextureSpace) -> Self {
+        self.space = space;
+        self
+    }
+}
+
+impl Texture for NoiseTexture {
+    fn color_at(&self, coords: &TextureCoords) -> Color {
+        let p = self.space.point(coords) * self.scale;
+        let mut value = 0.0;
+        let mut amplitude = 1.0;
+        let mut total = 0.0;
+        for octave in 0..self.octaves.max(1) {
+            value += self.noise.noise(&(p * 2f64.powi(octave as i32))) * amplitude;
+            total += amplitude;
+            amplitude *= 0.5;
+        }
+        mix(self.low, self.high, 0.5 + 0.5 * value / total)
+    }
+}
+
+/// Turbulence (summed absolute noise), giving billowy cloud or fire like patterns.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Turbulence {
+    noise: Perlin,
+    low: Color,
+    high: Color,
+    scale: f64,
+    octaves: usize,
+    space: TextureSpace,
+}
+
+impl Turbulence {
+    pub fn new<T: Into<Color>, U: Into<Color>>(low: T, high: U, scale: f64, octaves: usize) -> Self {
+        Self { noise: Perlin::new(0), low: low.into(), high: high.into(), scale, octaves, space: TextureSpace::World }
+    }
+
+    pub fn in_space(mut self, space: TextureSpace) -> Self {
+        self.space = space;
+        self
+    }
+}
+
+impl Texture for Turbulence {
+    fn color_at(&self, coords: &TextureCoords) -> Color {
+        let p = self.space.point(coords) * self.scale;
+        mix(self.low, self.high, self.noise.turbulence(&p, self.octaves))
+    }
+}
+
+/// Veins running across `direction`, distorted by turbulence.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Marble {
+    noise: Perlin,
+    base: Color,
+    vein: Color,
+    direction: Vector3,
+    frequency: f64,
+    distortion: f64,
+    space: TextureSpace,
+}
+
+impl Marble {
+    pub fn new<T: Into<Color>, U: Into<Color>>(base: T, vein: U, frequency: f64) -> Self {
+        Self {
+            noise: Perlin::new(0),
+            base: base.into(),
+            vein: vein.into(),
+            direction: (1.0, 0.0, 0.0).into(),
+            frequency,
+            distortion: 2.5,
+            space: TextureSpace::World,
+        }
+    }
+
+    /// Direction across which the veins repeat.
+    pub fn with_direction<T: Into<Vector3>>(mut self, direction: T) -> Self {
+        self.direction = direction.into().norm();
+        self
+    }
+
+    /// How much the turbulence bends the veins.
+    pub fn with_distortion(mut self, distortion: f64) -> Self {
+        self.distortion = distortion;
+        self
+    }
+
+    pub fn in_space(mut self, space: TextureSpace) -> Self {
+        self.space = space;
+        self
+    }
+}
+
+impl Texture for Marble {
+    fn color_at(&self, coords: &TextureCoords) -> Color {
+        let p = self.space.point(coords) * self.frequency;
+        let phase = p.dot(self.direction) + self.distortion * self.noise.turbulence(&p, 6);
+        // Sharpen the sine so the veins are thin and the base dominates
+        let vein = (1.0 - (0.5 + 0.5 * phase.sin())).powi(4);
+        mix(self.base, self.vein, vein)
+    }
+}
+
+/// Concentric rings around the y axis, slightly wobbled by noise.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Wood {
+    noise: Perlin,
+    light: Color,
+    dark: Color,
+    ring_spacing: f64,
+    wobble: f64,
+    space: TextureSpace,
+}
+
+impl Wood {
+    pub fn new<T: Into<Color>, U: Into<Color>>(light: T, dark: U, ring_spacing: f64) -> Self {
+        Self { noise: Perlin::new(0), light: light.into(), dark: dark.into(), ring_spacing, wobble: 0.3, space: TextureSpace::World }
+    }
+
+    /// Amount of noise added to the ring distance, in rings.
+    pub fn with_wobble(mut self, wobble: f64) -> Self {
+        self.wobble = wobble;
+        self
+    }
+
+    pub fn in_space(mut self, space: TextureSpace) -> Self {
+        self.space = space;
+        self
+    }
+}
+
+impl Texture for Wood {
+    fn color_at(&self, coords: &TextureCoords) -> Color {
+        let p = self.space.point(coords) / self.ring_spacing;
+        let radius = (p.get_x() * p.get_x() + p.get_z() * p.get_z()).sqrt();
+        let rings = radius + self.wobble * self.noise.noise(&(p * 0.5));
+        let t = rings - rings.floor();
+        // Sharp transition from late wood back to early wood at each ring boundary
+        mix(self.light, self.dark, t * t)
+    }
+}
+
+/// Linear blend between two colors along `direction`, from `start` to `start + length`.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Gradient {
+    from: Color,
+    to: Color,
+    start: Vector3,
+    direction: Vector3,
+    length: f64,
+    repeat: bool,
+    space: TextureSpace,
+}
+
+impl Gradient {
+    pub fn new<T: Into<Color>, U: Into<Color>, V: Into<Vector3>, W: Into<Vector3>>(from: T, to: U, start: V, direction: W, length: f64) -> Self {
+        Self {
+            from: from.into(),
+            to: to.into(),
+            start: start.into(),
+            direction: direction.into().norm(),
+            length,
+            repeat: false,
+            space: TextureSpace::World,
+        }
+    }
+
+    /// Restart the gradient every `length` instead of clamping to the end colors.
+    pub fn repeating(mut self) -> Self {
+        self.repeat = true;
+        self
+    }
+
+    pub fn in_space(mut self, space: TextureSpace) -> Self {
+        self.space = space;
+        self
+    }
+}
+
+impl Texture for Gradient {
+    fn color_at(&self, coords: &TextureCoords) -> Color {
+        let t = (self.space.point(coords) - self.start).dot(self.direction) / self.length;
+        let t = if self.repeat { t - t.floor() } else { t };
+        mix(self.from, self.to, t)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn at(x: f64, y: f64, z: f64) -> TextureCoords {
+        TextureCoords::new((0.0, 0.0), (x, y, z), (x, y, z))
+    }
+
+    #[test]
+    fn test_perlin_range() {
+        let perlin = Perlin::new(7);
+        assert_eq!(perlin.noise(&Vector3::new(3.0, -2.0, 5.0)), 0.0);
+        for i in 0..1000 {
+            let p = Vector3::new(i as f64 * 0.137, i as f64 * -0.071, i as f64 * 0.029);
+            assert!(perlin.noise(&p).abs() <= 1.1);
+        }
+    }
+
+    /// Samples a texture along a line, checking each gray level stays between `low` and `high`,
+    /// and returns the levels.
+    fn levels<T: Texture>(texture: &T, low: f64, high: f64) -> Vec<f64> {
+        (0..500).map(|i| {
+            let color = Vector3::from(texture.color_at(&at(i as f64 * 0.137, i as f64 * -0.071, i as f64 * 0.029)));
+            assert!(color.get_x() >= low - 1e-9 && color.get_x() <= high + 1e-9);
+            color.get_x()
+        }).collect()
+    }
+
+    /// Whether the levels actually vary instead of sitting at one value.
+    fn varies(levels: &[f64]) -> bool {
+        let (min, max) = levels.iter().fold((f64::INFINITY, f64::NEG_INFINITY), |(min, max), &l| (min.min(l), max.max(l)));
+        max - min > 0.1
+    }
+
+    #[test]
+    fn test_noise_texture() {
+        let noise = NoiseTexture::new((0.2, 0.2, 0.2), (0.6, 0.6, 0.6), 3.0).with_octaves(4);
+        let values = levels(&noise, 0.2, 0.6);
+        assert!(varies(&values));
+        assert_eq!(levels(&noise.clone(), 0.2, 0.6), values);
+        assert_ne!(levels(&noise.with_seed(5), 0.2, 0.6), values);
+    }
+
+    #[test]
+    fn test_turbulence() {
+        let turbulence = Turbulence::new((0.2, 0.2, 0.2), (0.6, 0.6, 0.6), 1.5, 4);
+        let values = levels(&turbulence, 0.2, 0.6);
+        assert!(varies(&values));
+        assert_eq!(levels(&turbulence.clone(), 0.2, 0.6), values);
+    }
+
+    #[test]
+    fn test_marble() {
+        let marble = Marble::new((0.8, 0.8, 0.8), (0.3, 0.3, 0.3), 0.3);
+        let values = levels(&marble, 0.3, 0.8);
+        assert!(varies(&values));
+        assert_eq!(levels(&marble.clone(), 0.3, 0.8), values);
+    }
+
+    #[test]
+    fn test_wood() {
+        let wood = Wood::new((0.7, 0.7, 0.7), (0.2, 0.2, 0.2), 0.5);
+        let values = levels(&wood, 0.2, 0.7);
+        assert!(varies(&values));
+        assert_eq!(levels(&wood.clone(), 0.2, 0.7), values);
+
+        // Rings stay put in the world unless the texture is moved to object space
+        let moved = TextureCoords::new((0.0, 0.0), (1.3, 0.0, 0.4), (0.1, 0.0, 0.1));
+        let world = TextureCoords::new((0.0, 0.0), (1.3, 0.0, 0.4), (1.3, 0.0, 0.4));
+        assert_eq!(wood.color_at(&moved), wood.color_at(&world));
+        assert_eq!(wood.clone().in_space(TextureSpace::Object).color_at(&moved), wood.color_at(&at(0.1, 0.0, 0.1)));
+        assert_ne!(wood.clone().in_space(TextureSpace::Object).color_at(&moved), wood.color_at(&moved));
+    }
+
+    #[test]
+    fn test_checkerboard() {
+        let checker = Checkerboard::new((1.0, 1.0, 1.0), (0.0, 0.0, 0.0), 1.0);
+        assert_eq!(checker.color_at(&at(0.5, 0.5, 0.5)), Color::new(1.0, 1.0, 1.0));
+        assert_eq!(checker.color_at(&at(1.5, 0.5, 0.5)), Color::new(0.0, 0.0, 0.0));
+        assert_eq!(checker.color_at(&at(-0.5, 0.5, 0.5)), Color::new(0.0, 0.0, 0.0));
+    }
+
+    #[test]
+    fn test_gradient() {
+        let gradient = Gradient::new((0.0, 0.0, 0.0), (1.0, 1.0, 1.0), (0.0, 0.0, 0.0), (0.0, 1.0, 0.0), 2.0);
+        assert_eq!(gradient.color_at(&at(5.0, 1.0, 0.0)), Color::new(0.5, 0.5, 0.5));
+        assert_eq!(gradient.color_at(&at(0.0, 3.0, 0.0)), Color::new(1.0, 1.0, 1.0));
+        assert_eq!(gradient.repeating().color_at(&at(0.0, 3.0, 0.0)), Color::new(0.5, 0.5, 0.5));
+    }
+}
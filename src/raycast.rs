@@ -1,15 +1,34 @@
-use crate::basics::{Vector3, Color};
-use crate::shapes::SceneObject;
+use crate::basics::{Vector3, Color, Material, Transform};
 use crate::scene::Scene;
+use crate::texture::TextureCoords;
 
+/// How far rays leaving a surface start off it along the normal and skip ahead, so they don't hit
+/// the surface they start on. Starting off the surface keeps grazing rays clear of it too.
+pub const SURFACE_EPSILON: f64 = 0.001;
+
+/// A ray that only counts hits with `t_min <= t <= t_max`, where t is the distance along the direction.
+#[derive(Copy, Clone, PartialEq, Debug)]
 pub struct Ray {
     origin: Vector3,
     direction: Vector3,
+    t_min: f64,
+    t_max: f64,
 }
 
 impl Ray {
     pub fn new<T: Into<Vector3>, U: Into<Vector3>>(origin: T, dir: U) -> Self {
-        Ray { origin: origin.into(), direction: dir.into().norm() }
+        Ray { origin: origin.into(), direction: dir.into().norm(), t_min: 0.0, t_max: f64::INFINITY }
+    }
+
+    /// A ray starting on a surface, ignoring hits closer than `SURFACE_EPSILON`.
+    pub fn from_surface<T: Into<Vector3>, U: Into<Vector3>>(origin: T, dir: U) -> Self {
+        Self::new(origin, dir).with_interval(SURFACE_EPSILON, f64::INFINITY)
+    }
+
+    pub fn with_interval(mut self, t_min: f64, t_max: f64) -> Self {
+        self.t_min = t_min;
+        self.t_max = t_max;
+        self
     }
 
     pub fn get_origin(&self) -> &Vector3 {
@@ -20,108 +39,361 @@ impl Ray {
         &self.direction
     }
 
-    pub fn cast<'a>(&self, scene: &'a Scene) -> Option<(Vector3, &'a Box<dyn SceneObject + Send + Sync>)> {
-        let mut hit: Vector3 = (10000.0, 10000.0, 10000.0).into(); //TODO: save as distance
-        let mut hit_object: Option<&Box<dyn SceneObject + Send + Sync>> = None;
-        for object in scene.get_objects() {
-            let found = object.ray_intersects(&self);
-            if found.is_some() {
-                if Vector3::dist_between(&self.origin, &found.unwrap()) < Vector3::dist_between(&self.origin, &hit)
-                    && Vector3::dist_between(&self.origin, &found.unwrap()) > 0.001 {
-                    hit = found.unwrap();
-                    hit_object = Some(object);
-                }
-            }
+    pub fn get_t_min(&self) -> f64 {
+        self.t_min
+    }
+
+    pub fn get_t_max(&self) -> f64 {
+        self.t_max
+    }
+
+    /// Whether `t` lies within the ray's interval.
+    pub fn contains(&self, t: f64) -> bool {
+        t >= self.t_min && t <= self.t_max
+    }
+
+    pub fn at(&self, t: f64) -> Vector3 {
+        self.origin + self.direction * t
+    }
+
+    /// This ray moved back through `transform`, into the space the transform starts from, together
+    /// with how much longer a unit of distance is there. The interval is rescaled to match.
+    pub fn inverse_transformed(&self, transform: &Transform) -> (Ray, f64) {
+        let inverse = transform.inverse();
+        let direction = inverse.vector(&self.direction);
+        let scale = direction.mag();
+        let local = Ray::new(inverse.point(&self.origin), direction).with_interval(self.t_min * scale, self.t_max * scale);
+        (local, scale)
+    }
+
+    /// The closest hit in the scene within the ray's interval.
+    pub fn cast<'a>(&self, scene: &'a Scene) -> Option<Hit<'a>> {
+        scene.get_root().intersect(self)
+    }
+
+    /// Whether anything in the scene is hit within the ray's interval. Stops at the first hit found,
+    /// which makes it cheaper than `cast` for shadow rays.
+    pub fn occluded(&self, scene: &Scene) -> bool {
+        scene.get_root().occludes(self)
+    }
+}
+
+/// Everything known about the point where a ray hit a surface.
+#[derive(Copy, Clone, Debug)]
+pub struct Hit<'a> {
+    t: f64,
+    position: Vector3,
+    normal: Vector3,
+    shading_normal: Vector3,
+    tangent: Vector3,
+    bitangent: Option<Vector3>,
+    uv: (f64, f64),
+    object_position: Vector3,
+    front_face: bool,
+    material: &'a Material,
+}
+
+impl<'a> Hit<'a> {
+    /// A hit `t` units along `ray`. `outward_normal` points out of the surface; the stored normals are
+    /// flipped to face the ray when it hits the back side.
+    pub fn new(ray: &Ray, t: f64, outward_normal: Vector3, material: &'a Material) -> Self {
+        let position = ray.at(t);
+        let outward_normal = outward_normal.norm();
+        let front_face = ray.direction.dot(outward_normal) <= 0.0;
+        let normal = if front_face { outward_normal } else { -outward_normal };
+        Self {
+            t,
+            position,
+            normal,
+            shading_normal: normal,
+            tangent: normal.perpendicular(),
+            bitangent: None,
+            uv: (0.0, 0.0),
+            object_position: position,
+            front_face,
+            material,
         }
-        match hit_object {
-            Some(e) => {
-                return Some((hit, e));
-            }
-            None => {
-                return None;
+    }
+
+    /// Sets a smoothed normal for lighting, given pointing out of the surface like the geometric one.
+    pub fn with_shading_normal(mut self, outward_normal: Vector3) -> Self {
+        let normal = outward_normal.norm();
+        self.shading_normal = if self.front_face { normal } else { -normal };
+        self
+    }
+
+    /// Sets the direction in which u increases along the surface.
+    pub fn with_tangent(mut self, tangent: Vector3) -> Self {
+        self.tangent = tangent;
+        self
+    }
+
+    /// Sets the direction in which v increases along the surface. Without it, v is taken to
+    /// increase along the normal crossed with the tangent.
+    pub fn with_bitangent(mut self, bitangent: Vector3) -> Self {
+        self.bitangent = Some(bitangent);
+        self
+    }
+
+    pub fn with_uv(mut self, uv: (f64, f64)) -> Self {
+        self.uv = uv;
+        self
+    }
+
+    /// Sets the hit position relative to the object's own origin.
+    pub fn with_object_position(mut self, object_position: Vector3) -> Self {
+        self.object_position = object_position;
+        self
+    }
+
+    /// Overrides which side of the surface was hit, for surfaces that bound a solid differently than
+    /// the shape they came from. The normals keep facing the ray.
+    pub fn with_front_face(mut self, front_face: bool) -> Self {
+        self.front_face = front_face;
+        self
+    }
+
+    /// Moves a hit found in an object's own space into the space `transform` leads to, where it lies
+    /// `t` units along `ray`. The object position is kept, so textures stay attached to the object.
+    pub fn transformed(mut self, ray: &Ray, t: f64, transform: &Transform) -> Self {
+        self.t = t;
+        self.position = ray.at(t);
+        self.normal = transform.normal(&self.normal).norm();
+        self.shading_normal = transform.normal(&self.shading_normal).norm();
+        self.tangent = transform.vector(&self.tangent).norm();
+        self.bitangent = self.bitangent.map(|bitangent| transform.vector(&bitangent).norm());
+        self
+    }
+
+    pub fn get_t(&self) -> f64 {
+        self.t
+    }
+
+    pub fn get_position(&self) -> &Vector3 {
+        &self.position
+    }
+
+    /// Geometric normal, facing the side the ray came from.
+    pub fn get_normal(&self) -> &Vector3 {
+        &self.normal
+    }
+
+    /// Normal to shade with, facing the side the ray came from.
+    pub fn get_shading_normal(&self) -> &Vector3 {
+        &self.shading_normal
+    }
+
+    pub fn get_tangent(&self) -> &Vector3 {
+        &self.tangent
+    }
+
+    pub fn get_bitangent(&self) -> Vector3 {
+        self.bitangent.unwrap_or_else(|| self.normal.cross(self.tangent))
+    }
+
+    pub fn get_uv(&self) -> (f64, f64) {
+        self.uv
+    }
+
+    pub fn get_object_position(&self) -> &Vector3 {
+        &self.object_position
+    }
+
+    /// Whether the ray hit the outside of the surface.
+    pub fn is_front_face(&self) -> bool {
+        self.front_face
+    }
+
+    pub fn get_material(&self) -> &'a Material {
+        self.material
+    }
+
+    pub fn texture_coords(&self) -> TextureCoords {
+        TextureCoords::new(self.uv, self.position, self.object_position)
+    }
+}
+
+/// A pseudo-random number in [0, 1) for sample stream `n` of `pixel`, used to shift a stratified
+/// grid of samples differently for each pixel so the grid doesn't show up as banding.
+pub fn pixel_hash(pixel: (usize, usize), n: usize) -> f64 {
+    ((n as f64 * 12.9898 + pixel.0 as f64 * 78.233 + pixel.1 as f64 * 37.719).sin() * 43758.5453).rem_euclid(1.0)
+}
+
+/// Ambient occlusion settings. Occlusion is estimated by casting `samples * samples` rays over the
+/// hemisphere around the surface normal and counting the ones that hit something within `radius`.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct AmbientOcclusion {
+    radius: f64,
+    samples: usize,
+}
+
+impl AmbientOcclusion {
+    pub fn new(radius: f64, samples: usize) -> Self {
+        Self { radius, samples }
+    }
+
+    pub fn get_radius(&self) -> f64 {
+        self.radius
+    }
+
+    pub fn get_samples(&self) -> usize {
+        self.samples
+    }
+
+    /// Returns how exposed `point` is, from 0.0 (fully occluded) to 1.0 (nothing within the radius).
+    /// The sample grid is shifted per `pixel`.
+    pub fn accessibility(&self, scene: &Scene, point: &Vector3, normal: &Vector3, pixel: (usize, usize)) -> f64 {
+        if self.samples == 0 {
+            return 1.0;
+        }
+
+        let normal = normal.norm();
+        let tangent = normal.perpendicular();
+        let bitangent = normal.cross(tangent);
+
+        let start = *point + normal * SURFACE_EPSILON;
+        let shift = (pixel_hash(pixel, 3), pixel_hash(pixel, 4));
+        let mut unoccluded = 0;
+        for i in 0..self.samples {
+            for j in 0..self.samples {
+                // Stratified cosine-weighted direction, one per grid cell
+                let u = ((i as f64 + 0.5) / self.samples as f64 + shift.0).rem_euclid(1.0);
+                let v = ((j as f64 + 0.5) / self.samples as f64 + shift.1).rem_euclid(1.0);
+                let r = u.sqrt();
+                let phi = 2.0 * std::f64::consts::PI * v;
+                let direction = tangent * (r * phi.cos()) + bitangent * (r * phi.sin()) + normal * (1.0 - u).sqrt();
+
+                let ray = Ray::new(start, direction).with_interval(SURFACE_EPSILON, self.radius);
+                if !ray.occluded(scene) {
+                    unoccluded += 1;
+                }
             }
         }
+
+        unoccluded as f64 / (self.samples * self.samples) as f64
     }
 }
 
+/// Shades rays cast for one pixel of the image.
+#[derive(Copy, Clone, PartialEq, Debug)]
 pub struct ColorRay {
-
+    pixel: (usize, usize),
 }
 
 impl ColorRay {
+    pub fn new(pixel: (usize, usize)) -> Self {
+        Self { pixel }
+    }
+
     pub fn cast(&self, scene: &Scene, ray: &Ray, depth: isize) -> Color {
         let ambient_light = Color::from((0.2, 0.2, 0.2));
 
-        let hit = ray.cast(scene);
         let mut col = Color::from((0.0, 0.0, 0.0));
-        if hit.is_none() {
-            //no color from reflection
-        } else {
-            let hit_obj = hit.unwrap();
-            let hit_normal = hit_obj.1.normal(&hit_obj.0);
+        if let Some(hit) = ray.cast(scene) {
+            let hit_normal = hit.normal;
+            let material = hit.material;
+            let coords = hit.texture_coords();
+            //Lighting uses the smoothed and mapped normal, offsets use the real one
+            let shading_normal = material.shading_normal(&hit.shading_normal, &hit.tangent, &hit.get_bitangent(), &coords);
             //Check for illumination
-            let mut illumination: Color = ambient_light;
+            let mut illumination: Color = match scene.get_ambient_occlusion() {
+                Some(ao) => ambient_light * ao.accessibility(scene, &hit.position, &hit_normal, self.pixel),
+                None => ambient_light,
+            };
             for light in scene.get_lights() {
                 let mut tot_illumination: Vector3 = (0.0, 0.0, 0.0).into();
 
-                let mut found_count = 0;
-                let mut x_off = -0.5;
-                let mut y_off;
-                for _ in 0..10 {
-                    y_off = -0.5;
-                    for _ in 0..10 {
-                        let light_ray_start = hit_obj.0 + hit_normal * 0.000000000001;
-                        let light_ray_direction = *light.get_position() - hit_obj.0;
-
-                        let rand_vec: Vector3 = (1.0, 0.0, 0.0).into();
-                        let dir1 = rand_vec.cross(light_ray_direction).norm();
-                        let dir2 = dir1.cross(light_ray_direction).norm();
-
-                        if Vector3::dist_between(light.get_position(), &(*light.get_position() + dir1 * x_off + dir2 * y_off)) <= light.get_radius() {
-                            found_count += 1;
-
-                            let ray_2 = Ray::new(light_ray_start, light_ray_direction + dir1 * x_off + dir2 * y_off);
-                            let light_hit = ray_2.cast(scene);
-                            let light_intensity_modifier = light_ray_direction.norm().dot(hit_normal.norm()).max(0.0);
-
-                            if light_hit.is_none() {
-                                tot_illumination = Vector3::from(light.get_intensity()) * light_intensity_modifier + tot_illumination;
-
-                            } else {
+                let samples = light.sample_points(&hit.position);
+                for &sample in &samples {
+                    let light_ray_start = hit.position + hit_normal * SURFACE_EPSILON;
+                    let light_ray_direction = sample - light_ray_start;
 
-                                let light_hit_obj = light_hit.unwrap();
+                    // Stop just short of the light so an area light's own fixture doesn't shadow it
+                    let ray_2 = Ray::new(light_ray_start, light_ray_direction)
+                        .with_interval(SURFACE_EPSILON, light_ray_direction.mag() - SURFACE_EPSILON);
+                    let to_light = (sample - hit.position).norm();
+                    let light_intensity_modifier = material.light_factor(&shading_normal, &hit.tangent, &to_light, &-ray.direction.norm());
 
-                                if Vector3::dist_between(&light_hit_obj.0, &hit_obj.0) > Vector3::dist_between(light.get_position(), &hit_obj.0) {
-                                    tot_illumination = Vector3::from(light.get_intensity()) * light_intensity_modifier + tot_illumination;
-                                }
-                            }
-                        }
-
-                        y_off += 1.0 / 10.0;
+                    if !ray_2.occluded(scene) {
+                        tot_illumination = Vector3::from(light.get_intensity()) * light_intensity_modifier + tot_illumination;
                     }
-
-                    x_off += 1.0 / 10.0;
                 }
 
-                tot_illumination /= 10.0 * 10.0;
+                tot_illumination /= samples.len() as f64;
 
                 illumination += tot_illumination.into();
             }
 
             let mut incoming_col = (0.0, 0.0, 0.0).into();
 
-            let reflectivity = hit_obj.1.get_material().get_reflectivity();
+            let reflectivity = material.get_reflectivity();
 
             if depth > 0 && reflectivity > 0.0 {
-                let c_ray = ColorRay {};
-                let out_ray = Ray::new(hit_obj.0, ray.direction.norm() - hit_normal * 2.0 * ray.direction.norm().dot(hit_normal.norm()));
-                c_ray.cast(scene, &out_ray, depth - 1);
-                incoming_col = c_ray.cast(scene, &out_ray, depth - 1);
+                let out_ray = Ray::from_surface(hit.position + hit_normal * SURFACE_EPSILON, ray.direction.norm() - shading_normal * 2.0 * ray.direction.norm().dot(shading_normal.norm()));
+                incoming_col = self.cast(scene, &out_ray, depth - 1);
             }
 
-            col = *hit_obj.1.get_material().get_color() * illumination * (1.0 - reflectivity) + incoming_col * reflectivity;
+            col = material.color_at(&coords) * illumination * (1.0 - reflectivity) + incoming_col * reflectivity;
         }
 
         col
     }
-}
\ No newline at end of file
+
+    /// Standalone ambient occlusion pass: white where the first hit is fully exposed, black where it is fully occluded.
+    pub fn cast_occlusion(&self, scene: &Scene, ray: &Ray, ao: &AmbientOcclusion) -> Color {
+        match ray.cast(scene) {
+            Some(hit) => {
+                let exposure = ao.accessibility(scene, &hit.position, &hit.normal, self.pixel);
+                Color::new(exposure, exposure, exposure)
+            }
+            None => Color::new(1.0, 1.0, 1.0),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::shapes::{Sphere, Quad, Light};
+
+    #[test]
+    fn test_accessibility() {
+        let ao = AmbientOcclusion::new(4.0, 4);
+        let point = Vector3::new(0.0, 0.0, 0.0);
+        let normal = Vector3::new(0.0, 1.0, 0.0);
+
+        let open = Scene::new((0.0, 0.0, 0.0));
+        let mut enclosed = Scene::new((0.0, 0.0, 0.0));
+        enclosed.add(Box::new(Sphere::new(1.0, (0.0, 0.0, 0.0), Material::new((1.0, 1.0, 1.0), 0.0))));
+
+        for pixel in [(0, 0), (17, 3), (250, 499)].iter() {
+            assert_eq!(ao.accessibility(&open, &point, &normal, *pixel), 1.0);
+            assert_eq!(ao.accessibility(&enclosed, &point, &normal, *pixel), 0.0);
+        }
+    }
+
+    #[test]
+    fn test_accessibility_jitter() {
+        // A sphere beside the point catches some of the samples, which ones depends on the pixel
+        let ao = AmbientOcclusion::new(4.0, 4);
+        let mut scene = Scene::new((0.0, 0.0, 0.0));
+        scene.add(Box::new(Sphere::new(1.0, (1.5, 0.5, 0.0), Material::new((1.0, 1.0, 1.0), 0.0))));
+        let point = Vector3::new(0.0, 0.0, 0.0);
+        let normal = Vector3::new(0.0, 1.0, 0.0);
+
+        let values: Vec<f64> = (0..8).map(|x| ao.accessibility(&scene, &point, &normal, (x, 0))).collect();
+        assert!(values.iter().all(|v| *v > 0.0 && *v < 1.0));
+        assert!(values.iter().any(|v| *v != values[0]));
+    }
+
+    #[test]
+    fn test_area_light_factor() {
+        // The light's center lies in the floor's plane, only the samples above it light the floor
+        let mut scene = Scene::new((0.0, 0.0, 0.0));
+        scene.add(Box::new(Quad::new((-1.0, 0.0, -1.0), (0.0, 0.0, 2.0), (2.0, 0.0, 0.0), Material::new((1.0, 1.0, 1.0), 0.0))));
+        scene.add_light(Light::rectangle((2.0, -2.0, -1.0), (0.0, 4.0, 0.0), (0.0, 0.0, 2.0), (1.0, 1.0, 1.0)));
+
+        let color = ColorRay::new((0, 0)).cast(&scene, &Ray::new((0.0, 5.0, 0.0), (0.0, -1.0, 0.0)), 0);
+        assert!(Vector3::from(color).get_x() > 0.3, "{:?}", color);
+    }
+}
@@ -1,16 +1,127 @@
-use crate::basics::{Vector3, Color};
+use crate::basics::{Vector3, Color, Material, Transform};
+use crate::mesh::Mesh;
 use crate::shapes::{SceneObject, Light};
-use crate::raycast::{Ray, ColorRay};
+use crate::raycast::{Ray, Hit, ColorRay, AmbientOcclusion, pixel_hash};
 use std::fs::File;
 use std::io::Write;
 use std::thread::JoinHandle;
 use std::thread;
 use std::sync::{Arc, mpsc, Mutex};
+use std::f64::consts::PI;
+
+/// What the camera writes out for each pixel.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum RenderPass {
+    /// Fully shaded image
+    Color,
+    /// Grayscale ambient occlusion of the first hit, using these settings
+    AmbientOcclusion(AmbientOcclusion),
+}
+
+/// A named node of the scene graph. Its objects and child groups are placed with the group's
+/// transform, on top of the transforms of the groups above it.
+pub struct Group {
+    name: String,
+    transform: Transform,
+    objects: Vec<Box<dyn SceneObject + Send + Sync>>,
+    groups: Vec<Group>,
+}
+
+impl Group {
+    pub fn new(name: &str) -> Self {
+        Self { name: name.to_string(), transform: Transform::identity(), objects: Vec::new(), groups: Vec::new() }
+    }
+
+    pub fn with_transform(mut self, transform: Transform) -> Self {
+        self.transform = transform;
+        self
+    }
+
+    pub fn set_transform(&mut self, transform: Transform) {
+        self.transform = transform;
+    }
+
+    pub fn get_transform(&self) -> &Transform {
+        &self.transform
+    }
+
+    pub fn get_name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn add(&mut self, object: Box<dyn SceneObject + Send + Sync>) {
+        self.objects.push(object);
+    }
+
+    /// Adds a child group. Names should be unique among siblings, or only the first can be found.
+    pub fn add_group(&mut self, group: Group) {
+        self.groups.push(group);
+    }
+
+    pub fn get_objects(&self) -> &Vec<Box<dyn SceneObject + Send + Sync>> {
+        &self.objects
+    }
+
+    pub fn get_groups(&self) -> &Vec<Group> {
+        &self.groups
+    }
+
+    /// The descendant at a path of group names separated by `/`, like `"car/wheels/front_left"`.
+    /// The empty path is the group itself.
+    pub fn find(&self, path: &str) -> Option<&Group> {
+        path.split('/').filter(|name| !name.is_empty())
+            .try_fold(self, |group, name| group.groups.iter().find(|child| child.name == name))
+    }
+
+    pub fn find_mut(&mut self, path: &str) -> Option<&mut Group> {
+        path.split('/').filter(|name| !name.is_empty())
+            .try_fold(self, |group, name| group.groups.iter_mut().find(|child| child.name == name))
+    }
+
+    /// The closest hit within the ray's interval among everything in the group, with the ray and
+    /// hit in the group's parent space.
+    pub fn intersect(&self, ray: &Ray) -> Option<Hit<'_>> {
+        if self.transform == Transform::identity() {
+            return self.intersect_local(ray);
+        }
+        let (local, scale) = ray.inverse_transformed(&self.transform);
+        let hit = self.intersect_local(&local)?;
+        let t = hit.get_t() / scale;
+        Some(hit.transformed(ray, t, &self.transform))
+    }
+
+    fn intersect_local(&self, ray: &Ray) -> Option<Hit<'_>> {
+        let mut closest: Option<Hit<'_>> = None;
+        let mut ray = *ray;
+        for object in &self.objects {
+            if let Some(found) = object.ray_intersects(&ray) {
+                // Anything further away than this hit can be skipped by the remaining objects
+                ray = ray.with_interval(ray.get_t_min(), found.get_t());
+                closest = Some(found);
+            }
+        }
+        for group in &self.groups {
+            if let Some(found) = group.intersect(&ray) {
+                ray = ray.with_interval(ray.get_t_min(), found.get_t());
+                closest = Some(found);
+            }
+        }
+        closest
+    }
+
+    /// Whether anything in the group is hit within the ray's interval.
+    pub fn occludes(&self, ray: &Ray) -> bool {
+        let local = if self.transform == Transform::identity() { *ray } else { ray.inverse_transformed(&self.transform).0 };
+        self.objects.iter().any(|object| object.occludes(&local)) || self.groups.iter().any(|group| group.occludes(&local))
+    }
+}
 
 pub struct Scene {
     camera: Camera,
     lights: Vec<Light>,
-    objects: Vec<Box<dyn SceneObject + Send + Sync>>,
+    root: Group,
+    ambient_occlusion: Option<AmbientOcclusion>,
+    render_pass: RenderPass,
 }
 
 impl Scene {
@@ -18,20 +129,52 @@ impl Scene {
         Self {
             camera: Camera::new(camera_position),
             lights: Vec::new(),
-            objects: Vec::new(),
+            root: Group::new(""),
+            ambient_occlusion: None,
+            render_pass: RenderPass::Color,
         }
     }
 
     pub fn add(&mut self, object: Box<dyn SceneObject + Send + Sync>) {
-        self.objects.push(object);
+        self.root.add(object);
+    }
+
+    /// The objects added directly to the scene, outside of any named group.
+    pub fn get_objects(&self) -> &Vec<Box<dyn SceneObject + Send + Sync>> {
+        self.root.get_objects()
+    }
+
+    pub fn add_group(&mut self, group: Group) {
+        self.root.add_group(group);
+    }
+
+    /// Adds every face of `mesh` to the scene as triangles.
+    pub fn add_mesh(&mut self, mesh: &Mesh, material: Material) {
+        for triangle in mesh.to_triangles(&material) {
+            self.add(Box::new(triangle));
+        }
+    }
+
+    pub fn set_camera(&mut self, camera: Camera) {
+        self.camera = camera;
     }
 
     pub fn add_light(&mut self, light: Light) {
         self.lights.push(light);
     }
 
-    pub fn get_objects(&self) -> &Vec<Box<dyn SceneObject + Send + Sync>> {
-        &self.objects
+    /// The unnamed group holding everything in the scene.
+    pub fn get_root(&self) -> &Group {
+        &self.root
+    }
+
+    /// The group at `path` below the root, see `Group::find`.
+    pub fn find(&self, path: &str) -> Option<&Group> {
+        self.root.find(path)
+    }
+
+    pub fn find_mut(&mut self, path: &str) -> Option<&mut Group> {
+        self.root.find_mut(path)
     }
 
     pub fn get_lights(&self) -> &Vec<Light> {
@@ -42,6 +185,23 @@ impl Scene {
         &self.camera
     }
 
+    /// Enables ambient occlusion, which darkens the ambient term in creases and contact areas.
+    pub fn set_ambient_occlusion(&mut self, ambient_occlusion: Option<AmbientOcclusion>) {
+        self.ambient_occlusion = ambient_occlusion;
+    }
+
+    pub fn get_ambient_occlusion(&self) -> Option<&AmbientOcclusion> {
+        self.ambient_occlusion.as_ref()
+    }
+
+    pub fn set_render_pass(&mut self, render_pass: RenderPass) {
+        self.render_pass = render_pass;
+    }
+
+    pub fn get_render_pass(&self) -> RenderPass {
+        self.render_pass
+    }
+
     pub fn render(scene: Self) {
         let self_ref = Arc::new(scene);
         self_ref.clone().camera.render(self_ref);
@@ -49,22 +209,136 @@ impl Scene {
     }
 }
 
+/// How the camera maps the screen onto rays.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Projection {
+    /// Rays spread out from the camera's position, with a 90 degree field of view.
+    Perspective,
+    /// Parallel rays from a square of this width around the camera's position, so sizes don't
+    /// change with distance. The width has to be positive.
+    Orthographic(f64),
+}
+
 #[derive(Copy, Clone, PartialEq, Debug)]
 pub struct Camera {
     //TODO: add facing direction
     position: Vector3,
+    projection: Projection,
+    aperture: f64,
+    focus_distance: f64,
+    blades: usize,
+    samples: usize,
 }
 
 impl Camera {
-    fn new<T: Into<Vector3>>(position: T) -> Self {
-        Self { position: position.into() }
+    /// A pinhole camera, where everything is in focus.
+    pub fn new<T: Into<Vector3>>(position: T) -> Self {
+        Self { position: position.into(), projection: Projection::Perspective, aperture: 0.0, focus_distance: 1.0, blades: 0, samples: 1 }
+    }
+
+    pub fn with_projection(mut self, projection: Projection) -> Self {
+        if let Projection::Orthographic(width) = projection {
+            assert!(width > 0.0, "orthographic view width has to be positive");
+        }
+        self.projection = projection;
+        self
+    }
+
+    /// Gives the camera a lens of radius `aperture`, focused on the plane `focus_distance` in
+    /// front of it. Things off that plane blur more the wider the aperture.
+    pub fn with_aperture(mut self, aperture: f64, focus_distance: f64) -> Self {
+        self.aperture = aperture;
+        self.focus_distance = focus_distance;
+        self
+    }
+
+    /// Shapes the aperture as a regular polygon with this many sides, which shows in the shape
+    /// of out of focus highlights. Fewer than three keeps it round.
+    pub fn with_blades(mut self, blades: usize) -> Self {
+        self.blades = blades;
+        self
+    }
+
+    /// Averages `samples * samples` rays over the lens for each pixel. Only cameras with an
+    /// aperture have a lens to sample, a pinhole always casts a single ray.
+    pub fn with_samples(mut self, samples: usize) -> Self {
+        self.samples = samples.max(1);
+        self
+    }
+
+    pub fn get_position(&self) -> &Vector3 {
+        &self.position
+    }
+
+    pub fn get_projection(&self) -> Projection {
+        self.projection
+    }
+
+    pub fn get_aperture(&self) -> f64 {
+        self.aperture
+    }
+
+    pub fn get_focus_distance(&self) -> f64 {
+        self.focus_distance
+    }
+
+    /// Maps a point of the unit square onto the aperture, centered on the origin with radius 1.
+    fn lens_point(&self, u: f64, v: f64) -> (f64, f64) {
+        if self.blades >= 3 {
+            // Pick one of the triangles between the center and two neighbouring corners, then a
+            // uniformly spread point in it
+            let scaled = u * self.blades as f64;
+            let sector = scaled.floor().min(self.blades as f64 - 1.0);
+            let (along, out) = (scaled - sector, v.sqrt());
+            let corner = |k: f64| {
+                let angle = PI / 2.0 + 2.0 * PI * k / self.blades as f64;
+                (angle.cos(), angle.sin())
+            };
+            let (a, b) = (corner(sector), corner(sector + 1.0));
+            return (out * (a.0 * (1.0 - along) + b.0 * along), out * (a.1 * (1.0 - along) + b.1 * along));
+        }
+        // Concentric mapping, which keeps the strata of the square evenly sized on the disk
+        let (x, y) = (2.0 * u - 1.0, 2.0 * v - 1.0);
+        if x == 0.0 && y == 0.0 {
+            return (0.0, 0.0);
+        }
+        let (radius, angle) = if x.abs() > y.abs() { (x, PI / 4.0 * (y / x)) } else { (y, PI / 2.0 - PI / 4.0 * (x / y)) };
+        (radius * angle.cos(), radius * angle.sin())
+    }
+
+    /// The rays for a point on the screen, where `screen` runs from -1 to 1 on both axes. A
+    /// pinhole needs one ray, a lens gets a stratified grid of them, offset differently for each
+    /// `pixel` so the pattern doesn't show.
+    pub fn rays(&self, screen: (f64, f64), pixel: (usize, usize)) -> Vec<Ray> {
+        let (center, direction) = match self.projection {
+            Projection::Perspective => (self.position, Vector3::new(screen.0, screen.1, 1.0)),
+            Projection::Orthographic(width) => (self.position + Vector3::new(screen.0, screen.1, 0.0) * (width / 2.0), Vector3::new(0.0, 0.0, 1.0)),
+        };
+        if self.aperture <= 0.0 {
+            return vec![Ray::new(center, direction)];
+        }
+
+        let focus = center + direction * self.focus_distance;
+        let shift = (pixel_hash(pixel, 1), pixel_hash(pixel, 2));
+        let step = 1.0 / self.samples as f64;
+        let mut rays = Vec::with_capacity(self.samples * self.samples);
+        for i in 0..self.samples {
+            for j in 0..self.samples {
+                let u = ((i as f64 + 0.5) * step + shift.0).rem_euclid(1.0);
+                let v = ((j as f64 + 0.5) * step + shift.1).rem_euclid(1.0);
+                let (x, y) = self.lens_point(u, v);
+                let origin = center + Vector3::new(x, y, 0.0) * self.aperture;
+                rays.push(Ray::new(origin, focus - origin));
+            }
+        }
+        rays
     }
 
     fn render(&self, scene: Arc<Scene>) {
         let dur = std::time::Instant::now();
         const SIZE: (usize, usize) = (500, 500);
         let mut file = File::create("output.ppm").unwrap();
-        file.write(format!("P3 {} {} 255\n", SIZE.0, SIZE.1).as_bytes()).unwrap();
+        file.write_all(format!("P3 {} {} 255\n", SIZE.0, SIZE.1).as_bytes()).unwrap();
         let mut x_theta;
         let mut y_theta = 1.0;
 
@@ -76,12 +350,7 @@ impl Camera {
         for x in 0..SIZE.1 {
             x_theta = -1.0;
             for y in 0..SIZE.0 {
-                let self_pos = self.position;
-                let scene_copy = scene.clone();
-                let x_t = x_theta;
-                let y_t = y_theta;
-
-                transmitter.send((x, y, x_theta, y_theta));
+                transmitter.send((x, y, x_theta, y_theta)).unwrap();
 
                 x_theta += 2.0 / (SIZE.0 as f64);
             }
@@ -91,10 +360,10 @@ impl Camera {
 
         let (out_transmitter, out_receiver) = mpsc::channel();
 
-        for i in 0..8 {
+        for _ in 0..8 {
             let own_receiver = ts_receiver.clone();
             let out_t = out_transmitter.clone();
-            let self_pos = self.position;
+            let camera = *self;
             let scene_copy = scene.clone();
 
             join_handles.push(thread::spawn(move || {
@@ -107,9 +376,17 @@ impl Camera {
                             let x_t = data.2;
                             let y_t = data.3;
 
-                            let col_ray = ColorRay {};
-                            let ray = Ray::new(self_pos, (x_t, y_t, 1.0));
-                            let col = col_ray.cast(scene_copy.as_ref(), &ray, 2);
+                            let col_ray = ColorRay::new((x, y));
+                            // Summed outside of Color, which would clamp the total
+                            let rays = camera.rays((x_t, y_t), (x, y));
+                            let mut total = Vector3::new(0.0, 0.0, 0.0);
+                            for ray in &rays {
+                                total += Vector3::from(match scene_copy.get_render_pass() {
+                                    RenderPass::AmbientOcclusion(ao) => col_ray.cast_occlusion(scene_copy.as_ref(), ray, &ao),
+                                    RenderPass::Color => col_ray.cast(scene_copy.as_ref(), ray, 2),
+                                });
+                            }
+                            let col: Color = (total / rays.len() as f64).into();
 
                             out_t.send((x, y, col)).unwrap();
                         }
@@ -124,11 +401,8 @@ impl Camera {
 
         let mut col_vec: Vec<Vec<Color>> = Vec::with_capacity(SIZE.0);
 
-        for i in 0..SIZE.0 {
-            col_vec.push(Vec::new());
-            for _ in 0..SIZE.1 {
-                col_vec[i].push(Color::default());
-            }
+        for _ in 0..SIZE.0 {
+            col_vec.push(vec![Color::default(); SIZE.1]);
         }
 
         let mut count = 0;
@@ -150,9 +424,9 @@ impl Camera {
 
         for row in col_vec {
             for color in row {
-                file.write(color.to_string().as_bytes()).unwrap();
+                file.write_all(color.to_string().as_bytes()).unwrap();
             }
-            file.write(b"\n").unwrap();
+            file.write_all(b"\n").unwrap();
         }
 
         println!("Rendered in {} s", dur.elapsed().as_micros() as f64 / 1000000.0);
@@ -169,4 +443,98 @@ impl Camera {
 
         col
     }*/
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::shapes::Sphere;
+
+    #[test]
+    fn test_camera_rays() {
+        let pinhole = Camera::new((0.0, 0.0, 0.0)).with_samples(4);
+        assert_eq!(pinhole.rays((0.5, 0.0), (0, 0)).len(), 1);
+
+        // Every ray through the lens passes through the same point on the focus plane
+        let lens = Camera::new((0.0, 1.0, 0.0)).with_aperture(0.5, 10.0).with_samples(4);
+        let rays = lens.rays((0.5, -0.2), (3, 7));
+        assert_eq!(rays.len(), 16);
+        let focus = Vector3::new(5.0, -1.0, 10.0);
+        for ray in &rays {
+            let offset = *ray.get_origin() - Vector3::new(0.0, 1.0, 0.0);
+            assert!(offset.mag() <= 0.5 + 1e-9 && offset.get_z() == 0.0);
+            assert!((ray.at(10.0 / ray.get_direction().get_z()) - focus).mag() < 1e-9);
+        }
+
+        // Four blades make a diamond with corners on the axes
+        let square = Camera::new((0.0, 0.0, 0.0)).with_aperture(1.0, 5.0).with_blades(4).with_samples(8);
+        let origins: Vec<Vector3> = square.rays((0.0, 0.0), (1, 2)).iter().map(|ray| *ray.get_origin()).collect();
+        assert!(origins.iter().all(|o| o.get_x().abs() + o.get_y().abs() <= 1.0 + 1e-9));
+        assert!(origins.iter().any(|o| o.get_x().abs() + o.get_y().abs() > 0.8));
+    }
+
+    #[test]
+    fn test_orthographic_rays() {
+        let camera = Camera::new((1.0, 2.0, 0.0)).with_projection(Projection::Orthographic(8.0));
+        let rays = camera.rays((0.5, -1.0), (0, 0));
+        assert_eq!(rays.len(), 1);
+        assert_eq!(*rays[0].get_origin(), Vector3::new(3.0, -2.0, 0.0));
+        assert_eq!(*rays[0].get_direction(), Vector3::new(0.0, 0.0, 1.0));
+
+        // With a lens the rays still meet on the focus plane, straight ahead of their pixel
+        let lens = camera.with_aperture(0.5, 10.0).with_samples(3);
+        for ray in lens.rays((0.5, -1.0), (0, 0)) {
+            assert!((ray.at(10.0 / ray.get_direction().get_z()) - Vector3::new(3.0, -2.0, 10.0)).mag() < 1e-9);
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "orthographic view width has to be positive")]
+    fn test_orthographic_width() {
+        Camera::new((0.0, 0.0, 0.0)).with_projection(Projection::Orthographic(0.0));
+    }
+
+    fn table() -> Group {
+        let material = Material::new((1.0, 1.0, 1.0), 0.0);
+        let mut legs = Group::new("legs").with_transform(Transform::translate((0.0, -1.0, 0.0)));
+        legs.add(Box::new(Sphere::new(0.5, (1.0, 0.0, 0.0), material.clone())));
+        legs.add(Box::new(Sphere::new(0.5, (-1.0, 0.0, 0.0), material.clone())));
+
+        let mut table = Group::new("table").with_transform(Transform::translate((0.0, 0.0, 10.0)));
+        table.add(Box::new(Sphere::new(1.0, (0.0, 0.0, 0.0), material)));
+        table.add_group(legs);
+        table
+    }
+
+    #[test]
+    fn test_group_find() {
+        let mut scene = Scene::new((0.0, 0.0, 0.0));
+        scene.add_group(table());
+
+        assert_eq!(scene.find("table/legs").unwrap().get_objects().len(), 2);
+        assert_eq!(scene.find("/table/").unwrap().get_name(), "table");
+        assert_eq!(scene.find("").unwrap().get_groups().len(), 1);
+        assert!(scene.get_objects().is_empty());
+        assert!(scene.find("table/top").is_none());
+
+        scene.find_mut("table/legs").unwrap().set_transform(Transform::translate((0.0, -2.0, 0.0)));
+        assert_eq!(*scene.find("table/legs").unwrap().get_transform(), Transform::translate((0.0, -2.0, 0.0)));
+    }
+
+    #[test]
+    fn test_group_intersect() {
+        let mut root = Group::new("");
+        root.add_group(table());
+
+        // The leg at x = 1 ends up at (1, -1, 10) after both transforms
+        let leg = root.intersect(&Ray::new((1.0, -1.0, 0.0), (0.0, 0.0, 1.0))).unwrap();
+        assert!((leg.get_t() - 9.5).abs() < 1e-9);
+        assert!((*leg.get_position() - Vector3::new(1.0, -1.0, 9.5)).mag() < 1e-9);
+        assert!(root.occludes(&Ray::new((-1.0, -1.0, 0.0), (0.0, 0.0, 1.0))));
+        assert!(!root.occludes(&Ray::new((1.0, -1.0, 0.0), (0.0, 0.0, 1.0)).with_interval(0.0, 9.0)));
+
+        // The closer table top wins over the leg behind it
+        let top = root.intersect(&Ray::new((0.0, 0.0, 0.0), (0.0, 0.0, 1.0))).unwrap();
+        assert!((top.get_t() - 9.0).abs() < 1e-9);
+    }
+}
@@ -0,0 +1,402 @@
+//! Shapes given by signed distance functions: the distance from a point to the closest surface,
+//! negative inside. They're intersected by sphere tracing, stepping along the ray by the distance
+//! to the surface, which never overshoots it.
+
+use crate::basics::{Vector3, Material};
+use crate::raycast::{Ray, Hit, SURFACE_EPSILON};
+use crate::shapes::SceneObject;
+
+pub trait DistanceField {
+    /// Signed distance from `p` to the surface. It may underestimate the distance, but never
+    /// overestimate it, or sphere tracing can step through the surface.
+    fn distance(&self, p: &Vector3) -> f64;
+    /// Texture coordinates for a point on the surface. Fractals put their orbit trap here, so it
+    /// can be colored with a texture looked up by uv.
+    fn uv(&self, _p: &Vector3) -> (f64, f64) {
+        (0.0, 0.0)
+    }
+}
+
+impl<F: Fn(&Vector3) -> f64> DistanceField for F {
+    fn distance(&self, p: &Vector3) -> f64 {
+        self(p)
+    }
+}
+
+type Field = Box<dyn DistanceField + Send + Sync>;
+
+fn abs(v: &Vector3) -> Vector3 {
+    Vector3::new(v.get_x().abs(), v.get_y().abs(), v.get_z().abs())
+}
+
+fn max(v: &Vector3, value: f64) -> Vector3 {
+    Vector3::new(v.get_x().max(value), v.get_y().max(value), v.get_z().max(value))
+}
+
+/// A sphere around the origin.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct SphereField {
+    radius: f64,
+}
+
+impl SphereField {
+    pub fn new(radius: f64) -> Self {
+        Self { radius }
+    }
+}
+
+impl DistanceField for SphereField {
+    fn distance(&self, p: &Vector3) -> f64 {
+        p.mag() - self.radius
+    }
+}
+
+/// An axis-aligned box around the origin, with optionally rounded edges.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct BoxField {
+    half_size: Vector3,
+    rounding: f64,
+}
+
+impl BoxField {
+    pub fn new<T: Into<Vector3>>(half_size: T) -> Self {
+        Self { half_size: half_size.into(), rounding: 0.0 }
+    }
+
+    /// Rounds off edges and corners with this radius, keeping the outer size.
+    pub fn with_rounding(mut self, rounding: f64) -> Self {
+        self.rounding = rounding;
+        self
+    }
+}
+
+impl DistanceField for BoxField {
+    fn distance(&self, p: &Vector3) -> f64 {
+        let q = abs(p) - self.half_size + Vector3::new(self.rounding, self.rounding, self.rounding);
+        let inside = q.get_x().max(q.get_y()).max(q.get_z()).min(0.0);
+        max(&q, 0.0).mag() + inside - self.rounding
+    }
+}
+
+/// A torus around the y axis through the origin.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct TorusField {
+    major_radius: f64,
+    minor_radius: f64,
+}
+
+impl TorusField {
+    pub fn new(major_radius: f64, minor_radius: f64) -> Self {
+        Self { major_radius, minor_radius }
+    }
+}
+
+impl DistanceField for TorusField {
+    fn distance(&self, p: &Vector3) -> f64 {
+        let ring = (p.get_x() * p.get_x() + p.get_z() * p.get_z()).sqrt() - self.major_radius;
+        (ring * ring + p.get_y() * p.get_y()).sqrt() - self.minor_radius
+    }
+}
+
+/// The points within `radius` of the segment between two points.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct CapsuleField {
+    start: Vector3,
+    end: Vector3,
+    radius: f64,
+}
+
+impl CapsuleField {
+    pub fn new<T: Into<Vector3>, U: Into<Vector3>>(start: T, end: U, radius: f64) -> Self {
+        Self { start: start.into(), end: end.into(), radius }
+    }
+}
+
+impl DistanceField for CapsuleField {
+    fn distance(&self, p: &Vector3) -> f64 {
+        let segment = self.end - self.start;
+        let offset = *p - self.start;
+        let along = (offset.dot(segment) / segment.dot(segment).max(1e-12)).clamp(0.0, 1.0);
+        (offset - segment * along).mag() - self.radius
+    }
+}
+
+/// A field moved away from the origin.
+pub struct Translated {
+    field: Field,
+    offset: Vector3,
+}
+
+impl Translated {
+    pub fn new<F: DistanceField + Send + Sync + 'static, T: Into<Vector3>>(field: F, offset: T) -> Self {
+        Self { field: Box::new(field), offset: offset.into() }
+    }
+}
+
+impl DistanceField for Translated {
+    fn distance(&self, p: &Vector3) -> f64 {
+        self.field.distance(&(*p - self.offset))
+    }
+
+    fn uv(&self, p: &Vector3) -> (f64, f64) {
+        self.field.uv(&(*p - self.offset))
+    }
+}
+
+/// A field uniformly scaled around the origin.
+pub struct Scaled {
+    field: Field,
+    factor: f64,
+}
+
+impl Scaled {
+    pub fn new<F: DistanceField + Send + Sync + 'static>(field: F, factor: f64) -> Self {
+        Self { field: Box::new(field), factor }
+    }
+}
+
+impl DistanceField for Scaled {
+    fn distance(&self, p: &Vector3) -> f64 {
+        self.field.distance(&(*p / self.factor)) * self.factor
+    }
+
+    fn uv(&self, p: &Vector3) -> (f64, f64) {
+        self.field.uv(&(*p / self.factor))
+    }
+}
+
+/// Two fields blended together, filling in the creases where they meet within `smoothness` of
+/// each other. A smoothness of zero is a plain union.
+pub struct SmoothUnion {
+    a: Field,
+    b: Field,
+    smoothness: f64,
+}
+
+impl SmoothUnion {
+    pub fn new<A: DistanceField + Send + Sync + 'static, B: DistanceField + Send + Sync + 'static>(a: A, b: B, smoothness: f64) -> Self {
+        Self { a: Box::new(a), b: Box::new(b), smoothness }
+    }
+}
+
+impl DistanceField for SmoothUnion {
+    fn distance(&self, p: &Vector3) -> f64 {
+        let (a, b) = (self.a.distance(p), self.b.distance(p));
+        if self.smoothness <= 0.0 {
+            return a.min(b);
+        }
+        // Polynomial smooth minimum
+        let h = (0.5 + 0.5 * (b - a) / self.smoothness).clamp(0.0, 1.0);
+        b * (1.0 - h) + a * h - self.smoothness * h * (1.0 - h)
+    }
+
+    fn uv(&self, p: &Vector3) -> (f64, f64) {
+        if self.a.distance(p) <= self.b.distance(p) {
+            self.a.uv(p)
+        } else {
+            self.b.uv(p)
+        }
+    }
+}
+
+/// Infinitely many copies of a field, one per cell of a grid centered on the origin. An axis with
+/// a period of zero isn't repeated. The copies should fit inside their cells.
+pub struct Repeat {
+    field: Field,
+    period: Vector3,
+}
+
+impl Repeat {
+    pub fn new<F: DistanceField + Send + Sync + 'static, T: Into<Vector3>>(field: F, period: T) -> Self {
+        Self { field: Box::new(field), period: period.into() }
+    }
+
+    /// Position relative to the center of the cell `p` is in.
+    fn cell_position(&self, p: &Vector3) -> Vector3 {
+        let wrap = |x: f64, period: f64| if period > 0.0 { x - period * (x / period).round() } else { x };
+        Vector3::new(
+            wrap(p.get_x(), self.period.get_x()),
+            wrap(p.get_y(), self.period.get_y()),
+            wrap(p.get_z(), self.period.get_z()),
+        )
+    }
+}
+
+impl DistanceField for Repeat {
+    fn distance(&self, p: &Vector3) -> f64 {
+        self.field.distance(&self.cell_position(p))
+    }
+
+    fn uv(&self, p: &Vector3) -> (f64, f64) {
+        self.field.uv(&self.cell_position(p))
+    }
+}
+
+/// A field twisted around the y axis by `rate` radians per unit of height. Twisting stretches
+/// distances, so objects using it need a step scale below one, see `SdfObject::with_step_scale`.
+pub struct Twist {
+    field: Field,
+    rate: f64,
+}
+
+impl Twist {
+    pub fn new<F: DistanceField + Send + Sync + 'static>(field: F, rate: f64) -> Self {
+        Self { field: Box::new(field), rate }
+    }
+
+    fn untwisted(&self, p: &Vector3) -> Vector3 {
+        let (sin, cos) = (self.rate * p.get_y()).sin_cos();
+        Vector3::new(cos * p.get_x() - sin * p.get_z(), p.get_y(), sin * p.get_x() + cos * p.get_z())
+    }
+}
+
+impl DistanceField for Twist {
+    fn distance(&self, p: &Vector3) -> f64 {
+        self.field.distance(&self.untwisted(p))
+    }
+
+    fn uv(&self, p: &Vector3) -> (f64, f64) {
+        self.field.uv(&self.untwisted(p))
+    }
+}
+
+/// A distance field placed in the scene. It's only traced inside a bounding sphere, which has to
+/// contain the whole surface.
+pub struct SdfObject {
+    field: Field,
+    center: Vector3,
+    bound: f64,
+    step_scale: f64,
+    max_steps: usize,
+    epsilon: f64,
+    material: Material,
+}
+
+impl SdfObject {
+    /// The field's origin is placed at `center`, with the surface within `bound` of it.
+    pub fn new<F: DistanceField + Send + Sync + 'static, T: Into<Vector3>>(field: F, center: T, bound: f64, material: Material) -> Self {
+        Self { field: Box::new(field), center: center.into(), bound, step_scale: 1.0, max_steps: 256, epsilon: 1e-5, material }
+    }
+
+    /// Scales every step down, for fields that overestimate distances.
+    pub fn with_step_scale(mut self, step_scale: f64) -> Self {
+        self.step_scale = step_scale;
+        self
+    }
+
+    /// Gives up on a ray after this many steps.
+    pub fn with_max_steps(mut self, max_steps: usize) -> Self {
+        self.max_steps = max_steps;
+        self
+    }
+
+    /// How close to the surface a step has to land to count as a hit. It has to stay below
+    /// `SURFACE_EPSILON`, or rays leaving the surface would hit it again right away.
+    pub fn with_epsilon(mut self, epsilon: f64) -> Self {
+        assert!(epsilon < SURFACE_EPSILON, "epsilon has to be smaller than SURFACE_EPSILON");
+        self.epsilon = epsilon;
+        self
+    }
+
+    fn distance(&self, ray: &Ray) -> Option<f64> {
+        // Only march through the part of the ray inside the bounding sphere
+        let offset = *ray.get_origin() - self.center;
+        let b = ray.get_direction().dot(offset);
+        let disc = b * b - (offset.dot(offset) - self.bound * self.bound);
+        if disc < 0.0 {
+            return None;
+        }
+        let end = (-b + disc.sqrt()).min(ray.get_t_max());
+        let mut t = (-b - disc.sqrt()).max(ray.get_t_min());
+
+        for _ in 0..self.max_steps {
+            if t > end {
+                return None;
+            }
+            let d = self.field.distance(&(offset + *ray.get_direction() * t)).abs();
+            if d < self.epsilon {
+                return Some(t);
+            }
+            t += d * self.step_scale;
+        }
+        None
+    }
+
+    /// The gradient of the field by central differences, which points out of the surface.
+    fn gradient(&self, local: &Vector3) -> Vector3 {
+        let h = self.epsilon * 10.0;
+        let diff = |axis: Vector3| self.field.distance(&(*local + axis * h)) - self.field.distance(&(*local - axis * h));
+        Vector3::new(diff(Vector3::new(1.0, 0.0, 0.0)), diff(Vector3::new(0.0, 1.0, 0.0)), diff(Vector3::new(0.0, 0.0, 1.0)))
+    }
+}
+
+impl SceneObject for SdfObject {
+    fn ray_intersects(&self, ray: &Ray) -> Option<Hit<'_>> {
+        let t = self.distance(ray)?;
+        let local = ray.at(t) - self.center;
+        Some(Hit::new(ray, t, self.gradient(&local), &self.material)
+            .with_uv(self.field.uv(&local))
+            .with_object_position(local))
+    }
+
+    fn occludes(&self, ray: &Ray) -> bool {
+        self.distance(ray).is_some()
+    }
+
+    fn get_material(&self) -> &Material {
+        &self.material
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_fields() {
+        assert!((BoxField::new((1.0, 2.0, 3.0)).distance(&Vector3::new(3.0, 0.0, 0.0)) - 2.0).abs() < 1e-9);
+        assert!((BoxField::new((1.0, 2.0, 3.0)).distance(&Vector3::new(0.0, 0.0, 0.0)) + 1.0).abs() < 1e-9);
+        assert!((TorusField::new(3.0, 1.0).distance(&Vector3::new(0.0, 0.0, 0.0)) - 2.0).abs() < 1e-9);
+        assert!((CapsuleField::new((0.0, 0.0, 0.0), (0.0, 2.0, 0.0), 0.5).distance(&Vector3::new(1.0, 1.0, 0.0)) - 0.5).abs() < 1e-9);
+
+        let repeated = Repeat::new(SphereField::new(0.5), (2.0, 0.0, 0.0));
+        assert!((repeated.distance(&Vector3::new(10.0, 0.0, 0.0)) + 0.5).abs() < 1e-9);
+        assert!((repeated.distance(&Vector3::new(10.0, 2.0, 0.0)) - 1.5).abs() < 1e-9);
+
+        // Blending pulls the surface outwards between two spheres
+        let a = Translated::new(SphereField::new(1.0), (-1.0, 0.0, 0.0));
+        let b = Translated::new(SphereField::new(1.0), (1.0, 0.0, 0.0));
+        let crease = Vector3::new(0.0, 0.5, 0.0);
+        let hard = a.distance(&crease).min(b.distance(&crease));
+        assert!(SmoothUnion::new(a, b, 0.5).distance(&crease) < hard);
+
+        // A quarter turn per unit of height swaps x and z one unit up
+        let twisted = Twist::new(BoxField::new((2.0, 10.0, 0.5)), std::f64::consts::FRAC_PI_2);
+        assert!(twisted.distance(&Vector3::new(1.5, 0.0, 0.0)) < 0.0);
+        assert!(twisted.distance(&Vector3::new(1.5, 1.0, 0.0)) > 0.0);
+    }
+
+    #[test]
+    fn test_sdf_intersect() {
+        let material = Material::new((1.0, 0.0, 0.0), 0.0);
+        let sphere = SdfObject::new(SphereField::new(2.0), (0.0, 0.0, 10.0), 2.5, material.clone());
+        let hit = sphere.ray_intersects(&Ray::new((0.0, 0.0, 0.0), (0.0, 0.0, 1.0))).unwrap();
+        assert!((hit.get_t() - 8.0).abs() < 1e-4);
+        assert!((*hit.get_normal() - Vector3::new(0.0, 0.0, -1.0)).mag() < 1e-4);
+        assert!(sphere.ray_intersects(&Ray::new((0.0, 3.0, 0.0), (0.0, 0.0, 1.0))).is_none());
+        assert!(!sphere.occludes(&Ray::new((0.0, 0.0, 0.0), (0.0, 0.0, 1.0)).with_interval(0.0, 7.0)));
+        // Rays leaving the surface start off it, so even grazing ones don't hit it again
+        let coarse = SdfObject::new(SphereField::new(2.0), (0.0, 0.0, 10.0), 2.5, material.clone()).with_epsilon(5e-4);
+        let leaving = Ray::from_surface(*hit.get_position() + *hit.get_normal() * SURFACE_EPSILON, (0.0, 1.0, -0.001));
+        assert!(!sphere.occludes(&leaving));
+        assert!(!coarse.occludes(&leaving));
+        // A shadow ray from a floor touching the sphere still finds it
+        let floor_point = Vector3::new(0.1, -2.0, 10.0);
+        assert!(coarse.occludes(&Ray::from_surface(floor_point + Vector3::new(0.0, SURFACE_EPSILON, 0.0), (0.0, 1.0, 0.0))));
+
+        // Closures work as fields too
+        let plane = SdfObject::new(|p: &Vector3| p.get_y(), (0.0, -1.0, 5.0), 3.0, material);
+        let hit = plane.ray_intersects(&Ray::new((0.0, 0.0, 5.0), (0.0, -1.0, 0.0))).unwrap();
+        assert!((hit.get_t() - 1.0).abs() < 1e-4);
+    }
+}
@@ -1,12 +1,49 @@
-use crate::raycast::Ray;
-use crate::basics::{Vector3, Material, Color};
+use crate::raycast::{Ray, Hit};
+use crate::basics::{Vector3, Material, Color, Transform};
+use crate::solver::{solve_quadratic, solve_quartic};
+use std::sync::Arc;
 
 pub trait SceneObject {
-    fn ray_intersects(&self, ray: &Ray) -> Option<Vector3>;
-    fn normal(&self, point: &Vector3) -> Vector3;
+    /// The closest point within the ray's interval where `ray` hits the object, if any.
+    fn ray_intersects(&self, ray: &Ray) -> Option<Hit<'_>>;
+    /// Whether `ray` hits the object anywhere within its interval. Shapes can override this with
+    /// something cheaper than finding the closest hit.
+    fn occludes(&self, ray: &Ray) -> bool {
+        self.ray_intersects(ray).is_some()
+    }
+    /// All spans of the ray's line that lie inside the object, as entry and exit hits sorted by
+    /// distance. The ray's interval is ignored. Only closed shapes have an inside, so the default
+    /// is no spans at all.
+    fn intervals(&self, _ray: &Ray) -> Vec<Span<'_>> {
+        Vec::new()
+    }
     fn get_material(&self) -> &Material;
 }
 
+/// Where a ray enters an object and where it leaves it again.
+pub type Span<'a> = (Hit<'a>, Hit<'a>);
+
+/// The ray with its interval opened up to the whole line.
+fn whole_line(ray: &Ray) -> Ray {
+    ray.with_interval(f64::NEG_INFINITY, f64::INFINITY)
+}
+
+/// Pairs up the surface crossings of a closed shape into spans, from each entry to the next exit.
+/// A ray only grazing the surface can leave crossings that don't fit, which are dropped.
+fn pair_crossings(mut crossings: Vec<Hit<'_>>) -> Vec<Span<'_>> {
+    crossings.sort_by(|a, b| a.get_t().partial_cmp(&b.get_t()).unwrap());
+    let mut spans = Vec::new();
+    let mut entry = None;
+    for crossing in crossings {
+        if crossing.is_front_face() {
+            entry = Some(crossing);
+        } else if let Some(entry) = entry.take() {
+            spans.push((entry, crossing));
+        }
+    }
+    spans
+}
+
 #[derive(PartialEq, Debug, Clone)]
 pub struct Sphere {
     radius: f64,
@@ -18,52 +55,66 @@ impl Sphere {
     pub fn new<T: Into<Vector3>>(radius: f64, center: T, material: Material) -> Self {
         Self { radius, center: center.into(), material }
     }
-}
 
-impl SceneObject for Sphere {
-    fn ray_intersects(&self, ray: &Ray) -> Option<Vector3> {
+    fn uv(&self, dir: &Vector3) -> (f64, f64) {
+        // Longitude around the y axis and latitude from the south to the north pole
+        let u = 0.5 + dir.get_z().atan2(dir.get_x()) / (2.0 * std::f64::consts::PI);
+        let v = 0.5 + dir.get_y().clamp(-1.0, 1.0).asin() / std::f64::consts::PI;
+        (u, v)
+    }
+
+    fn tangent(&self, dir: &Vector3) -> Vector3 {
+        let tangent = Vector3::new(-dir.get_z(), 0.0, dir.get_x());
+        if tangent.mag() < 1e-9 {
+            // u is undefined at the poles
+            dir.perpendicular()
+        } else {
+            tangent.norm()
+        }
+    }
+
+    /// Distances to the near and far side along the ray's line, if it hits at all.
+    fn distances(&self, ray: &Ray) -> Option<(f64, f64)> {
+        let offset = *ray.get_origin() - self.center;
         let a = ray.get_direction().dot(*ray.get_direction());
-        let b = ((*ray.get_direction() * 2.0) as Vector3).dot(*ray.get_origin() - self.center);
-        let c = (*ray.get_origin() - self.center).dot(*ray.get_origin() - self.center) - self.radius * self.radius;
+        let b = 2.0 * ray.get_direction().dot(offset);
+        let c = offset.dot(offset) - self.radius * self.radius;
 
         let disc = b * b - 4.0 * a * c;
-        if disc < 0.001 {
+        if disc < 0.0 {
             return None;
         }
-        if disc > -0.001 && disc < 0.001 {
-            let x = -b / (2.0 * a);
-            if x >= 0.0 {
-                return Some(*ray.get_origin() + *ray.get_direction() * x);
-            } else {
-                return None;
-            }
-        }
-        if disc > 0.001 {
-            let x1 = -b / (2.0 * a) + disc.sqrt() / (2.0 * a);
-            let x2 = -b / (2.0 * a) - disc.sqrt() / (2.0 * a);
-            if x1 < 0.0 && x2 < 0.0 {
-                return None;
-            }
-            if x1 < 0.0 || x2 < 0.0 {
-                return Some(*ray.get_origin() + *ray.get_direction() * x1.max(x2));
-            }
-            if x1 >= 0.0 && x2 >= 0.0 {
-                return Some(*ray.get_origin() + *ray.get_direction() * x1.min(x2));
-            }
-        }
-        println!();
-        println!("disc: {}", disc);
-        println!("a: {}, b: {}, c:{}", a, b, c);
-        println!("ray direction: {:?}", ray.get_direction());
-        panic!("aaaa quadratic bad");
+        Some(((-b - disc.sqrt()) / (2.0 * a), (-b + disc.sqrt()) / (2.0 * a)))
     }
 
-    fn normal(&self, point: &Vector3) -> Vector3 {
-        if (Vector3::dist_between(point, &self.center) - self.radius).abs() >= 0.001 {
-            println!("{}", ((*point - self.center).mag() - self.radius).abs());
-            panic!("point isn't on sphere");
+    fn hit_at<'a>(&'a self, ray: &Ray, t: f64) -> Hit<'a> {
+        let position = ray.at(t);
+        let dir = (position - self.center) / self.radius;
+        let tangent = self.tangent(&dir);
+        // v increases towards the north pole
+        Hit::new(ray, t, dir, &self.material)
+            .with_uv(self.uv(&dir))
+            .with_tangent(tangent)
+            .with_bitangent(tangent.cross(dir))
+            .with_object_position(position - self.center)
+    }
+}
+
+impl SceneObject for Sphere {
+    fn ray_intersects(&self, ray: &Ray) -> Option<Hit<'_>> {
+        let (near, far) = self.distances(ray)?;
+        if ray.contains(near) {
+            Some(self.hit_at(ray, near))
+        } else if ray.contains(far) {
+            Some(self.hit_at(ray, far))
+        } else {
+            None
         }
-        (*point - self.center).norm()
+    }
+
+    fn intervals(&self, ray: &Ray) -> Vec<Span<'_>> {
+        let line = whole_line(ray);
+        self.distances(&line).map(|(near, far)| (self.hit_at(&line, near), self.hit_at(&line, far))).into_iter().collect()
     }
 
     fn get_material(&self) -> &Material {
@@ -76,6 +127,7 @@ pub struct Plane {
     coefficients: Vector3,
     scalar: f64,
     point_on: Vector3,
+    u_axis: Vector3,
     material: Material,
 }
 
@@ -89,35 +141,44 @@ impl Plane {
         assert!(p1v.dot(coefficients) - scalar < 0.001);
         assert!(p2v.dot(coefficients) - scalar < 0.001);
         assert!(p3v.dot(coefficients) - scalar < 0.001);
-        Self { coefficients, scalar, material, point_on: p1v }
+        Self { coefficients, scalar, material, point_on: p1v, u_axis: (p2v - p1v).norm() }
     }
-}
 
-impl SceneObject for Plane {
-    fn ray_intersects(&self, ray: &Ray) -> Option<Vector3> {
-        if ray.get_direction().dot(self.coefficients) < 0.001 && ray.get_direction().dot(self.coefficients) > -0.001 {
-            if ray.get_origin().dot(self.coefficients) - self.scalar < 0.001 {
-                return Some(*ray.get_origin());
-            } else {
-                return None;
-            }
+    /// Distance along the ray to the plane, if the ray isn't parallel to it and the hit is within the ray's interval.
+    fn ray_distance(&self, ray: &Ray) -> Option<f64> {
+        // Relative to the normal's length, which grows with the size of the plane's defining points
+        let facing = ray.get_direction().dot(self.coefficients);
+        if facing.abs() < 0.001 * self.coefficients.mag() {
+            return None;
+        }
+        let t = (self.point_on - *ray.get_origin()).dot(self.coefficients) / facing;
+        if ray.contains(t) {
+            Some(t)
         } else {
-            let point_on_plane = self.point_on;
-            let n = (point_on_plane - *ray.get_origin()).dot(self.coefficients) / ray.get_direction().dot(self.coefficients);
-            if n >= 0.0 {
-                return Some(*ray.get_origin() + *ray.get_direction() * n);
-            } else {
-                return None;
-            }
+            None
         }
-        panic!("aaaa bad ray intersect");
     }
 
-    fn normal(&self, point: &Vector3) -> Vector3 {
-        if point.dot(self.coefficients) - self.scalar >= 0.001 {
-            panic!("point isn't on plane");
-        }
-        self.coefficients.norm()
+    fn v_axis(&self) -> Vector3 {
+        self.coefficients.norm().cross(self.u_axis)
+    }
+
+    fn uv(&self, point: &Vector3) -> (f64, f64) {
+        // Distances along the first edge and its in-plane perpendicular, in world units
+        let offset = *point - self.point_on;
+        (offset.dot(self.u_axis), offset.dot(self.v_axis()))
+    }
+}
+
+impl SceneObject for Plane {
+    fn ray_intersects(&self, ray: &Ray) -> Option<Hit<'_>> {
+        let t = self.ray_distance(ray)?;
+        let position = ray.at(t);
+        Some(Hit::new(ray, t, self.coefficients, &self.material)
+            .with_uv(self.uv(&position))
+            .with_tangent(self.u_axis)
+            .with_bitangent(self.v_axis())
+            .with_object_position(position - self.point_on))
     }
 
     fn get_material(&self) -> &Material {
@@ -127,6 +188,8 @@ impl SceneObject for Plane {
 
 pub struct Triangle {
     points: (Vector3, Vector3, Vector3),
+    uvs: ((f64, f64), (f64, f64), (f64, f64)),
+    normals: Option<(Vector3, Vector3, Vector3)>,
     plane: Plane,
 }
 
@@ -139,51 +202,711 @@ impl Triangle {
 
         Self {
             points: (p1v, p2v, p3v),
+            uvs: ((0.0, 0.0), (1.0, 0.0), (0.0, 1.0)),
+            normals: None,
             plane,
         }
     }
 
-    fn inside(&self, point: &Vector3) -> bool {
-        (self.points.1 - self.points.0).cross(*point - self.points.0).dot(self.plane.coefficients) > 0.0 &&
-            (self.points.2 - self.points.1).cross(*point - self.points.1).dot(self.plane.coefficients) > 0.0 &&
-            (self.points.0 - self.points.2).cross(*point - self.points.2).dot(self.plane.coefficients) > 0.0
+    /// Sets the texture coordinates of the three corners, in the same order as the points.
+    pub fn with_uvs(mut self, uv1: (f64, f64), uv2: (f64, f64), uv3: (f64, f64)) -> Self {
+        self.uvs = (uv1, uv2, uv3);
+        self
+    }
+
+    /// Sets per-vertex normals, which are interpolated across the triangle for smooth shading.
+    pub fn with_normals<T: Into<Vector3>, U: Into<Vector3>, V: Into<Vector3>>(mut self, n1: T, n2: U, n3: V) -> Self {
+        self.normals = Some((n1.into().norm(), n2.into().norm(), n3.into().norm()));
+        self
+    }
+
+    /// Barycentric weights of `point` with respect to the three corners.
+    fn barycentric(&self, point: &Vector3) -> (f64, f64, f64) {
+        let area = self.plane.coefficients.dot(self.plane.coefficients);
+        let w1 = (self.points.2 - self.points.1).cross(*point - self.points.1).dot(self.plane.coefficients) / area;
+        let w2 = (self.points.0 - self.points.2).cross(*point - self.points.2).dot(self.plane.coefficients) / area;
+        (w1, w2, 1.0 - w1 - w2)
+    }
+
+    /// Directions in which u and v increase across the triangle.
+    fn tangents(&self) -> (Vector3, Vector3) {
+        let edge1 = self.points.1 - self.points.0;
+        let edge2 = self.points.2 - self.points.0;
+        let (du1, dv1) = (self.uvs.1.0 - self.uvs.0.0, self.uvs.1.1 - self.uvs.0.1);
+        let (du2, dv2) = (self.uvs.2.0 - self.uvs.0.0, self.uvs.2.1 - self.uvs.0.1);
+        let det = du1 * dv2 - du2 * dv1;
+        if det.abs() < 1e-12 {
+            (edge1.norm(), self.plane.v_axis())
+        } else {
+            (((edge1 * dv2 - edge2 * dv1) / det).norm(), ((edge2 * du1 - edge1 * du2) / det).norm())
+        }
     }
 }
 
 impl SceneObject for Triangle {
-    fn ray_intersects(&self, ray: &Ray) -> Option<Vector3> {
-        match self.plane.ray_intersects(ray) {
-            Some(point) => {
-                if self.inside(&point) {
-                    return Some(point);
-                } else {
+    fn ray_intersects(&self, ray: &Ray) -> Option<Hit<'_>> {
+        let t = self.plane.ray_distance(ray)?;
+        let position = ray.at(t);
+        let (w1, w2, w3) = self.barycentric(&position);
+        if w1 <= 0.0 || w2 <= 0.0 || w3 <= 0.0 {
+            return None;
+        }
+
+        let uv = (
+            self.uvs.0.0 * w1 + self.uvs.1.0 * w2 + self.uvs.2.0 * w3,
+            self.uvs.0.1 * w1 + self.uvs.1.1 * w2 + self.uvs.2.1 * w3,
+        );
+        let (tangent, bitangent) = self.tangents();
+        let hit = Hit::new(ray, t, self.plane.coefficients, &self.plane.material)
+            .with_uv(uv)
+            .with_tangent(tangent)
+            .with_bitangent(bitangent)
+            .with_object_position(position - self.points.0);
+        match self.normals {
+            Some((n1, n2, n3)) => Some(hit.with_shading_normal(n1 * w1 + n2 * w2 + n3 * w3)),
+            None => Some(hit),
+        }
+    }
+
+    fn get_material(&self) -> &Material {
+        &self.plane.material
+    }
+}
+
+/// Distance along a ray, axis index and side (-1.0 or 1.0) of a box face crossing.
+type SlabCrossing = (f64, usize, f64);
+
+/// A box with faces along its own three axes, which start out aligned with the world axes.
+#[derive(PartialEq, Debug, Clone)]
+pub struct Cuboid {
+    center: Vector3,
+    half_size: [f64; 3],
+    axes: [Vector3; 3],
+    material: Material,
+}
+
+impl Cuboid {
+    /// An axis-aligned box between two opposite corners.
+    pub fn new<T: Into<Vector3>, U: Into<Vector3>>(corner1: T, corner2: U, material: Material) -> Self {
+        let c1 = corner1.into();
+        let c2 = corner2.into();
+        let size = c2 - c1;
+        Self {
+            center: (c1 + c2) / 2.0,
+            half_size: [size.get_x().abs() / 2.0, size.get_y().abs() / 2.0, size.get_z().abs() / 2.0],
+            axes: [(1.0, 0.0, 0.0).into(), (0.0, 1.0, 0.0).into(), (0.0, 0.0, 1.0).into()],
+            material,
+        }
+    }
+
+    /// Rotates the box `angle` radians around `axis` through its center.
+    pub fn rotated<T: Into<Vector3>>(mut self, axis: T, angle: f64) -> Self {
+        let axis = axis.into();
+        for a in self.axes.iter_mut() {
+            *a = a.rotate_around(&axis, angle).norm();
+        }
+        self
+    }
+
+    /// Entry and exit distances along the ray through the three slabs, with the axis index and
+    /// side of the face crossed at each.
+    fn slabs(&self, ray: &Ray) -> Option<(SlabCrossing, SlabCrossing)> {
+        let offset = *ray.get_origin() - self.center;
+        let mut near = (f64::NEG_INFINITY, 0, 0.0);
+        let mut far = (f64::INFINITY, 0, 0.0);
+        for i in 0..3 {
+            let origin = offset.dot(self.axes[i]);
+            let direction = ray.get_direction().dot(self.axes[i]);
+            if direction.abs() < 1e-12 {
+                if origin.abs() > self.half_size[i] {
                     return None;
                 }
+                continue;
             }
-            None => {
-                return None;
+            let t1 = (-self.half_size[i] - origin) / direction;
+            let t2 = (self.half_size[i] - origin) / direction;
+            let (entry, exit) = if t1 < t2 { ((t1, i, -1.0), (t2, i, 1.0)) } else { ((t2, i, 1.0), (t1, i, -1.0)) };
+            if entry.0 > near.0 {
+                near = entry;
             }
+            if exit.0 < far.0 {
+                far = exit;
+            }
+        }
+        if near.0 > far.0 {
+            None
+        } else {
+            Some((near, far))
         }
     }
 
-    fn normal(&self, point: &Vector3) -> Vector3 {
-        if self.inside(point) {
-            return self.plane.normal(point);
+    fn face_hit<'a>(&'a self, ray: &Ray, (t, axis, side): SlabCrossing) -> Hit<'a> {
+        // Each face is textured with v along a fixed up axis and u completing a right handed frame with the normal
+        let up = if axis == 1 { 2 } else { 1 };
+        let across = 3 - axis - up;
+        let u_sign = if axis == 2 { side } else { -side };
+        let local = ray.at(t) - self.center;
+        let along = |i: usize| local.dot(self.axes[i]) / self.half_size[i].max(1e-12);
+        let uv = (0.5 + 0.5 * u_sign * along(across), 0.5 + 0.5 * along(up));
+
+        Hit::new(ray, t, self.axes[axis] * side, &self.material)
+            .with_uv(uv)
+            .with_tangent(self.axes[across] * u_sign)
+            .with_bitangent(self.axes[up])
+            .with_object_position(local)
+    }
+}
+
+impl SceneObject for Cuboid {
+    fn ray_intersects(&self, ray: &Ray) -> Option<Hit<'_>> {
+        let (near, far) = self.slabs(ray)?;
+        if ray.contains(near.0) {
+            Some(self.face_hit(ray, near))
+        } else if ray.contains(far.0) {
+            Some(self.face_hit(ray, far))
         } else {
-            panic!();
+            None
         }
     }
 
+    fn intervals(&self, ray: &Ray) -> Vec<Span<'_>> {
+        let line = whole_line(ray);
+        self.slabs(&line).map(|(near, far)| (self.face_hit(&line, near), self.face_hit(&line, far))).into_iter().collect()
+    }
+
     fn get_material(&self) -> &Material {
-        &self.plane.material
+        &self.material
+    }
+}
+
+/// A flat circle.
+#[derive(PartialEq, Debug, Clone)]
+pub struct Disk {
+    center: Vector3,
+    normal: Vector3,
+    radius: f64,
+    material: Material,
+}
+
+impl Disk {
+    pub fn new<T: Into<Vector3>, U: Into<Vector3>>(center: T, normal: U, radius: f64, material: Material) -> Self {
+        let normal = normal.into();
+        assert!(normal.mag() > 0.0, "disk normal has zero length");
+        Self { center: center.into(), normal: normal.norm(), radius, material }
+    }
+
+    fn distance(&self, ray: &Ray) -> Option<f64> {
+        let facing = ray.get_direction().dot(self.normal);
+        if facing.abs() < 1e-12 {
+            return None;
+        }
+        let t = (self.center - *ray.get_origin()).dot(self.normal) / facing;
+        if ray.contains(t) && (ray.at(t) - self.center).mag() <= self.radius {
+            Some(t)
+        } else {
+            None
+        }
+    }
+
+    fn hit_at<'a>(&'a self, ray: &Ray, t: f64) -> Hit<'a> {
+        // Polar coordinates: u goes around the center, v outwards from it
+        let local = ray.at(t) - self.center;
+        let e1 = self.normal.perpendicular();
+        let e2 = self.normal.cross(e1);
+        let angle = local.dot(e2).atan2(local.dot(e1));
+        let uv = (angle.rem_euclid(2.0 * std::f64::consts::PI) / (2.0 * std::f64::consts::PI), local.mag() / self.radius);
+        Hit::new(ray, t, self.normal, &self.material)
+            .with_uv(uv)
+            .with_tangent(e2 * angle.cos() - e1 * angle.sin())
+            .with_bitangent(e1 * angle.cos() + e2 * angle.sin())
+            .with_object_position(local)
+    }
+}
+
+impl SceneObject for Disk {
+    fn ray_intersects(&self, ray: &Ray) -> Option<Hit<'_>> {
+        self.distance(ray).map(|t| self.hit_at(ray, t))
+    }
+
+    fn get_material(&self) -> &Material {
+        &self.material
+    }
+}
+
+/// A cone between two circles of different radii around an axis, closed off by disks at both ends.
+/// With a top radius of zero it comes to a point, otherwise it's truncated.
+#[derive(PartialEq, Debug, Clone)]
+pub struct Cone {
+    base: Vector3,
+    axis: Vector3,
+    height: f64,
+    base_radius: f64,
+    top_radius: f64,
+    caps: Vec<Disk>,
+    material: Material,
+}
+
+impl Cone {
+    pub fn new<T: Into<Vector3>, U: Into<Vector3>>(base: T, top: U, base_radius: f64, top_radius: f64, material: Material) -> Self {
+        let base = base.into();
+        let top = top.into();
+        assert!((top - base).mag() > 0.0, "base and top are the same point");
+        let axis = (top - base).norm();
+        let mut caps = Vec::new();
+        if base_radius > 0.0 {
+            caps.push(Disk::new(base, -axis, base_radius, material.clone()));
+        }
+        if top_radius > 0.0 {
+            caps.push(Disk::new(top, axis, top_radius, material.clone()));
+        }
+        Self { base, axis, height: (top - base).mag(), base_radius, top_radius, caps, material }
+    }
+
+    /// Removes the end caps, leaving only the slanted side.
+    pub fn without_caps(mut self) -> Self {
+        self.caps.clear();
+        self
+    }
+
+    /// Distances to the hits on the side within the ray's interval, closest first.
+    fn side_distances(&self, ray: &Ray) -> Vec<f64> {
+        let slope = (self.top_radius - self.base_radius) / self.height;
+        let offset = *ray.get_origin() - self.base;
+        let origin_height = offset.dot(self.axis);
+        let direction_height = ray.get_direction().dot(self.axis);
+        // Components of the origin and direction perpendicular to the axis
+        let radial_origin = offset - self.axis * origin_height;
+        let radial_direction = *ray.get_direction() - self.axis * direction_height;
+        let radius_at_origin = self.base_radius + slope * origin_height;
+
+        let a = radial_direction.dot(radial_direction) - slope * slope * direction_height * direction_height;
+        let b = 2.0 * (radial_origin.dot(radial_direction) - slope * direction_height * radius_at_origin);
+        let c = radial_origin.dot(radial_origin) - radius_at_origin * radius_at_origin;
+
+        let roots = if a.abs() < 1e-12 {
+            if b.abs() < 1e-12 {
+                return Vec::new();
+            }
+            vec![-c / b]
+        } else {
+            let disc = b * b - 4.0 * a * c;
+            if disc < 0.0 {
+                return Vec::new();
+            }
+            let (t1, t2) = ((-b - disc.sqrt()) / (2.0 * a), (-b + disc.sqrt()) / (2.0 * a));
+            vec![t1.min(t2), t1.max(t2)]
+        };
+
+        roots.into_iter().filter(|&t| {
+            let h = origin_height + t * direction_height;
+            // Skip the mirrored half of the double cone past the tip
+            ray.contains(t) && h >= 0.0 && h <= self.height && self.base_radius + slope * h >= 0.0
+        }).collect()
+    }
+
+    fn side_hit<'a>(&'a self, ray: &Ray, t: f64) -> Hit<'a> {
+        let slope = (self.top_radius - self.base_radius) / self.height;
+        let local = ray.at(t) - self.base;
+        let h = local.dot(self.axis);
+        let radial = local - self.axis * h;
+        let radial_dir = if radial.mag() > 1e-12 { radial.norm() } else { self.axis.perpendicular() };
+
+        let e1 = self.axis.perpendicular();
+        let e2 = self.axis.cross(e1);
+        let angle = radial_dir.dot(e2).atan2(radial_dir.dot(e1));
+        let uv = (angle.rem_euclid(2.0 * std::f64::consts::PI) / (2.0 * std::f64::consts::PI), h / self.height);
+
+        Hit::new(ray, t, radial_dir - self.axis * slope, &self.material)
+            .with_uv(uv)
+            .with_tangent(self.axis.cross(radial_dir))
+            .with_bitangent(self.axis + radial_dir * slope)
+            .with_object_position(local)
+    }
+}
+
+impl SceneObject for Cone {
+    fn ray_intersects(&self, ray: &Ray) -> Option<Hit<'_>> {
+        let mut closest = self.side_distances(ray).first().map(|&t| self.side_hit(ray, t));
+        for cap in &self.caps {
+            let limited = ray.with_interval(ray.get_t_min(), closest.map_or(ray.get_t_max(), |hit| hit.get_t()));
+            if let Some(hit) = cap.ray_intersects(&limited) {
+                closest = Some(hit);
+            }
+        }
+        closest
+    }
+
+    fn occludes(&self, ray: &Ray) -> bool {
+        !self.side_distances(ray).is_empty() || self.caps.iter().any(|cap| cap.distance(ray).is_some())
+    }
+
+    fn intervals(&self, ray: &Ray) -> Vec<Span<'_>> {
+        // Without its caps the cone is open and has no inside
+        if self.caps.is_empty() {
+            return Vec::new();
+        }
+        let line = whole_line(ray);
+        let mut crossings: Vec<Hit<'_>> = self.side_distances(&line).into_iter().map(|t| self.side_hit(&line, t)).collect();
+        crossings.extend(self.caps.iter().filter_map(|cap| cap.distance(&line).map(|t| cap.hit_at(&line, t))));
+        pair_crossings(crossings)
+    }
+
+    fn get_material(&self) -> &Material {
+        &self.material
+    }
+}
+
+/// A capped cylinder between two points.
+#[derive(PartialEq, Debug, Clone)]
+pub struct Cylinder {
+    cone: Cone,
+}
+
+impl Cylinder {
+    pub fn new<T: Into<Vector3>, U: Into<Vector3>>(base: T, top: U, radius: f64, material: Material) -> Self {
+        Self { cone: Cone::new(base, top, radius, radius, material) }
+    }
+
+    /// Removes the end caps, leaving an open tube.
+    pub fn without_caps(self) -> Self {
+        Self { cone: self.cone.without_caps() }
+    }
+}
+
+impl SceneObject for Cylinder {
+    fn ray_intersects(&self, ray: &Ray) -> Option<Hit<'_>> {
+        self.cone.ray_intersects(ray)
+    }
+
+    fn occludes(&self, ray: &Ray) -> bool {
+        self.cone.occludes(ray)
+    }
+
+    fn intervals(&self, ray: &Ray) -> Vec<Span<'_>> {
+        self.cone.intervals(ray)
+    }
+
+    fn get_material(&self) -> &Material {
+        self.cone.get_material()
+    }
+}
+
+/// A ring shaped surface: the points at distance `minor_radius` from a circle of radius `major_radius`.
+#[derive(PartialEq, Debug, Clone)]
+pub struct Torus {
+    center: Vector3,
+    axis: Vector3,
+    major_radius: f64,
+    minor_radius: f64,
+    material: Material,
+}
+
+impl Torus {
+    /// A torus around `axis`, which passes through the hole.
+    pub fn new<T: Into<Vector3>, U: Into<Vector3>>(center: T, axis: U, major_radius: f64, minor_radius: f64, material: Material) -> Self {
+        Self { center: center.into(), axis: axis.into().norm(), major_radius, minor_radius, material }
+    }
+
+    /// Coordinates relative to the center, with y along the axis.
+    fn to_local(&self, vec: &Vector3) -> Vector3 {
+        let e1 = self.axis.perpendicular();
+        let e2 = e1.cross(self.axis);
+        Vector3::new(vec.dot(e1), vec.dot(self.axis), vec.dot(e2))
+    }
+
+    fn to_world(&self, vec: &Vector3) -> Vector3 {
+        let e1 = self.axis.perpendicular();
+        let e2 = e1.cross(self.axis);
+        e1 * vec.get_x() + self.axis * vec.get_y() + e2 * vec.get_z()
+    }
+
+    /// Distances to the hits within the ray's interval, closest first.
+    fn distances(&self, ray: &Ray) -> Vec<f64> {
+        // Skip rays missing the bounding sphere, and start the others at it so the quartic's
+        // coefficients stay small for faraway origins
+        let bound = self.major_radius + self.minor_radius;
+        let offset = *ray.get_origin() - self.center;
+        let b = ray.get_direction().dot(offset);
+        let disc = b * b - (offset.dot(offset) - bound * bound);
+        if disc < 0.0 {
+            return Vec::new();
+        }
+        let start = (-b - disc.sqrt()).max(ray.get_t_min());
+        if start > ray.get_t_max() || start > -b + disc.sqrt() {
+            return Vec::new();
+        }
+
+        let o = self.to_local(&(offset + *ray.get_direction() * start));
+        let d = self.to_local(ray.get_direction());
+        let r2 = self.major_radius * self.major_radius;
+        let e = o.dot(o) + r2 - self.minor_radius * self.minor_radius;
+        let f = o.dot(d);
+        let flat_dd = d.get_x() * d.get_x() + d.get_z() * d.get_z();
+        let flat_od = o.get_x() * d.get_x() + o.get_z() * d.get_z();
+        let flat_oo = o.get_x() * o.get_x() + o.get_z() * o.get_z();
+
+        // (|p|^2 + R^2 - r^2)^2 = 4 R^2 (x^2 + z^2) along p = o + t d, with |d| = 1
+        solve_quartic(
+            1.0,
+            4.0 * f,
+            4.0 * f * f + 2.0 * e - 4.0 * r2 * flat_dd,
+            4.0 * f * e - 8.0 * r2 * flat_od,
+            e * e - 4.0 * r2 * flat_oo,
+        ).into_iter().map(|t| t + start).filter(|&t| ray.contains(t)).collect()
+    }
+
+    fn hit_at<'a>(&'a self, ray: &Ray, t: f64) -> Hit<'a> {
+        let local = self.to_local(&(ray.at(t) - self.center));
+
+        // Closest point on the center circle; the normal points away from it
+        let ring_distance = (local.get_x() * local.get_x() + local.get_z() * local.get_z()).sqrt();
+        let ring_point = if ring_distance > 1e-12 {
+            Vector3::new(local.get_x(), 0.0, local.get_z()) * (self.major_radius / ring_distance)
+        } else {
+            Vector3::new(self.major_radius, 0.0, 0.0)
+        };
+        let normal = local - ring_point;
+
+        // u goes around the axis, v around the tube
+        let tau = 2.0 * std::f64::consts::PI;
+        let u = local.get_z().atan2(local.get_x()).rem_euclid(tau) / tau;
+        let v = local.get_y().atan2(ring_distance - self.major_radius).rem_euclid(tau) / tau;
+        let tangent = Vector3::new(-local.get_z(), 0.0, local.get_x());
+        let angle = v * tau;
+        let bitangent = Vector3::new(0.0, angle.cos(), 0.0) - ring_point * (angle.sin() / self.major_radius);
+
+        Hit::new(ray, t, self.to_world(&normal), &self.material)
+            .with_uv((u, v))
+            .with_tangent(self.to_world(&tangent))
+            .with_bitangent(self.to_world(&bitangent))
+            .with_object_position(ray.at(t) - self.center)
+    }
+}
+
+impl SceneObject for Torus {
+    fn ray_intersects(&self, ray: &Ray) -> Option<Hit<'_>> {
+        self.distances(ray).first().map(|&t| self.hit_at(ray, t))
+    }
+
+    fn occludes(&self, ray: &Ray) -> bool {
+        !self.distances(ray).is_empty()
+    }
+
+    fn intervals(&self, ray: &Ray) -> Vec<Span<'_>> {
+        let line = whole_line(ray);
+        pair_crossings(self.distances(&line).into_iter().map(|t| self.hit_at(&line, t)).collect())
+    }
+
+    fn get_material(&self) -> &Material {
+        &self.material
+    }
+}
+
+/// The surface where a second degree polynomial in x, y and z is zero, which covers ellipsoids,
+/// paraboloids, hyperboloids, cones and elliptic cylinders. Many of them reach out to infinity, so
+/// they can be clipped to a box.
+#[derive(PartialEq, Debug, Clone)]
+pub struct Quadric {
+    coefficients: [f64; 10],
+    clip_box: Option<(Vector3, Vector3)>,
+    material: Material,
+}
+
+impl Quadric {
+    /// Coefficients of `A x^2 + B y^2 + C z^2 + D xy + E xz + F yz + G x + H y + I z + J`, in that
+    /// order. Normals point towards the side where the polynomial is positive.
+    pub fn new(coefficients: [f64; 10], material: Material) -> Self {
+        Self { coefficients, clip_box: None, material }
+    }
+
+    /// Only keeps the part of the surface inside the box between two opposite corners.
+    pub fn with_clip_box<T: Into<Vector3>, U: Into<Vector3>>(mut self, corner1: T, corner2: U) -> Self {
+        let (c1, c2) = (corner1.into(), corner2.into());
+        let low = Vector3::new(c1.get_x().min(c2.get_x()), c1.get_y().min(c2.get_y()), c1.get_z().min(c2.get_z()));
+        let high = Vector3::new(c1.get_x().max(c2.get_x()), c1.get_y().max(c2.get_y()), c1.get_z().max(c2.get_z()));
+        self.clip_box = Some((low, high));
+        self
+    }
+
+    fn in_clip_box(&self, p: &Vector3) -> bool {
+        self.clip_box.is_none_or(|(low, high)| {
+            (low.get_x()..=high.get_x()).contains(&p.get_x())
+                && (low.get_y()..=high.get_y()).contains(&p.get_y())
+                && (low.get_z()..=high.get_z()).contains(&p.get_z())
+        })
+    }
+
+    fn distance(&self, ray: &Ray) -> Option<f64> {
+        let [a, b, c, d, e, f, g, h, i, j] = self.coefficients;
+        let (o, dir) = (ray.get_origin(), ray.get_direction());
+        let (ox, oy, oz) = (o.get_x(), o.get_y(), o.get_z());
+        let (dx, dy, dz) = (dir.get_x(), dir.get_y(), dir.get_z());
+
+        // Substituting the ray into the polynomial gives a quadratic in t
+        let quadratic = a * dx * dx + b * dy * dy + c * dz * dz + d * dx * dy + e * dx * dz + f * dy * dz;
+        let linear = 2.0 * (a * ox * dx + b * oy * dy + c * oz * dz)
+            + d * (ox * dy + oy * dx) + e * (ox * dz + oz * dx) + f * (oy * dz + oz * dy)
+            + g * dx + h * dy + i * dz;
+        let constant = a * ox * ox + b * oy * oy + c * oz * oz + d * ox * oy + e * ox * oz + f * oy * oz
+            + g * ox + h * oy + i * oz + j;
+
+        solve_quadratic(quadratic, linear, constant).into_iter()
+            .find(|&t| ray.contains(t) && self.in_clip_box(&ray.at(t)))
+    }
+
+    fn gradient(&self, p: &Vector3) -> Vector3 {
+        let [a, b, c, d, e, f, g, h, i, _] = self.coefficients;
+        let (x, y, z) = (p.get_x(), p.get_y(), p.get_z());
+        Vector3::new(
+            2.0 * a * x + d * y + e * z + g,
+            2.0 * b * y + d * x + f * z + h,
+            2.0 * c * z + e * x + f * y + i,
+        )
     }
 }
 
+impl SceneObject for Quadric {
+    fn ray_intersects(&self, ray: &Ray) -> Option<Hit<'_>> {
+        let t = self.distance(ray)?;
+        let position = ray.at(t);
+        Some(Hit::new(ray, t, self.gradient(&position), &self.material))
+    }
+
+    fn occludes(&self, ray: &Ray) -> bool {
+        self.distance(ray).is_some()
+    }
+
+    fn get_material(&self) -> &Material {
+        &self.material
+    }
+}
+
+/// A flat parallelogram spanned by two edges from a corner. With perpendicular edges it's a rectangle.
+#[derive(PartialEq, Debug, Clone)]
+pub struct Quad {
+    corner: Vector3,
+    edge_u: Vector3,
+    edge_v: Vector3,
+    normal: Vector3,
+    material: Material,
+}
+
+impl Quad {
+    /// The normal points along `edge_u` x `edge_v`.
+    pub fn new<T: Into<Vector3>, U: Into<Vector3>, V: Into<Vector3>>(corner: T, edge_u: U, edge_v: V, material: Material) -> Self {
+        let edge_u = edge_u.into();
+        let edge_v = edge_v.into();
+        Self { corner: corner.into(), edge_u, edge_v, normal: edge_u.cross(edge_v), material }
+    }
+
+    pub fn get_corner(&self) -> &Vector3 {
+        &self.corner
+    }
+
+    pub fn get_edges(&self) -> (Vector3, Vector3) {
+        (self.edge_u, self.edge_v)
+    }
+
+    /// Distance along the ray and the position on the quad in units of its edges.
+    fn locate(&self, ray: &Ray) -> Option<(f64, f64, f64)> {
+        let facing = ray.get_direction().dot(self.normal);
+        if facing.abs() < 1e-12 {
+            return None;
+        }
+        let t = (self.corner - *ray.get_origin()).dot(self.normal) / facing;
+        if !ray.contains(t) {
+            return None;
+        }
+        let offset = ray.at(t) - self.corner;
+        let w = self.normal / self.normal.dot(self.normal);
+        let alpha = w.dot(offset.cross(self.edge_v));
+        let beta = w.dot(self.edge_u.cross(offset));
+        if (0.0..=1.0).contains(&alpha) && (0.0..=1.0).contains(&beta) {
+            Some((t, alpha, beta))
+        } else {
+            None
+        }
+    }
+}
+
+impl SceneObject for Quad {
+    fn ray_intersects(&self, ray: &Ray) -> Option<Hit<'_>> {
+        let (t, alpha, beta) = self.locate(ray)?;
+        Some(Hit::new(ray, t, self.normal, &self.material)
+            .with_uv((alpha, beta))
+            .with_tangent(self.edge_u.norm())
+            .with_bitangent(self.edge_v.norm())
+            .with_object_position(ray.at(t) - self.corner))
+    }
+
+    fn occludes(&self, ray: &Ray) -> bool {
+        self.locate(ray).is_some()
+    }
+
+    fn get_material(&self) -> &Material {
+        &self.material
+    }
+}
+
+/// A shared object placed in the scene with a transform, so the same shape can show up many times
+/// without being copied. Rays are moved into the object's space instead of moving the object.
+#[derive(Clone)]
+pub struct Instance {
+    object: Arc<dyn SceneObject + Send + Sync>,
+    transform: Transform,
+}
+
+impl Instance {
+    pub fn new(object: Arc<dyn SceneObject + Send + Sync>, transform: Transform) -> Self {
+        Self { object, transform }
+    }
+
+    pub fn get_transform(&self) -> &Transform {
+        &self.transform
+    }
+}
+
+impl SceneObject for Instance {
+    fn ray_intersects(&self, ray: &Ray) -> Option<Hit<'_>> {
+        let (local, scale) = ray.inverse_transformed(&self.transform);
+        let hit = self.object.ray_intersects(&local)?;
+        let t = hit.get_t() / scale;
+        Some(hit.transformed(ray, t, &self.transform))
+    }
+
+    fn occludes(&self, ray: &Ray) -> bool {
+        self.object.occludes(&ray.inverse_transformed(&self.transform).0)
+    }
+
+    fn intervals(&self, ray: &Ray) -> Vec<Span<'_>> {
+        let (local, scale) = ray.inverse_transformed(&self.transform);
+        self.object.intervals(&local).into_iter().map(|(entry, exit)| (
+            entry.transformed(ray, entry.get_t() / scale, &self.transform),
+            exit.transformed(ray, exit.get_t() / scale, &self.transform),
+        )).collect()
+    }
+
+    fn get_material(&self) -> &Material {
+        self.object.get_material()
+    }
+}
+
+/// Number of samples along each side of the grid used for soft shadows.
+pub const LIGHT_SAMPLES: usize = 10;
+
+#[derive(PartialEq, Debug, Copy, Clone)]
+enum LightShape {
+    /// A ball, seen from any point as a disk of this radius
+    Sphere(f64),
+    /// A parallelogram centered on the light's position
+    Rectangle(Vector3, Vector3),
+}
+
 #[derive(PartialEq, Debug, Copy, Clone)]
 pub struct Light {
     position: Vector3,
     intensity: Color,
-    radius: f64,
+    shape: LightShape,
 }
 
 impl Light {
@@ -191,10 +914,26 @@ impl Light {
         Self {
             position: pos.into(),
             intensity: intensity.into(),
-            radius,
+            shape: LightShape::Sphere(radius),
+        }
+    }
+
+    /// An area light covering the parallelogram spanned by two edges from a corner.
+    pub fn rectangle<T: Into<Vector3>, U: Into<Vector3>, V: Into<Vector3>, W: Into<Color>>(corner: T, edge_u: U, edge_v: V, intensity: W) -> Self {
+        let edge_u = edge_u.into();
+        let edge_v = edge_v.into();
+        Self {
+            position: corner.into() + (edge_u + edge_v) / 2.0,
+            intensity: intensity.into(),
+            shape: LightShape::Rectangle(edge_u, edge_v),
         }
     }
 
+    /// An area light with the same shape as `quad`, so the quad can act as the visible light fixture.
+    pub fn from_quad<T: Into<Color>>(quad: &Quad, intensity: T) -> Self {
+        Self::rectangle(quad.corner, quad.edge_u, quad.edge_v, intensity)
+    }
+
     pub fn get_position(&self) -> &Vector3 {
         &self.position
     }
@@ -203,23 +942,279 @@ impl Light {
         &self.intensity
     }
 
+    /// Radius of a sphere light, or the distance from the center to the corners of a rectangular one.
     pub fn get_radius(&self) -> f64 {
-        self.radius
+        match self.shape {
+            LightShape::Sphere(radius) => radius,
+            LightShape::Rectangle(edge_u, edge_v) => (edge_u + edge_v).mag().max((edge_u - edge_v).mag()) / 2.0,
+        }
+    }
+
+    /// Points on the light to cast shadow rays at from `from`, taken from a
+    /// `LIGHT_SAMPLES` x `LIGHT_SAMPLES` grid. Sphere lights only use the grid points inside their
+    /// radius, so they can return fewer points, and just their center when seen from it.
+    pub fn sample_points(&self, from: &Vector3) -> Vec<Vector3> {
+        let step = 1.0 / LIGHT_SAMPLES as f64;
+        let mut points = Vec::with_capacity(LIGHT_SAMPLES * LIGHT_SAMPLES);
+        match self.shape {
+            LightShape::Sphere(radius) => {
+                // A grid across the light's diameter, facing `from`
+                let direction = self.position - *from;
+                if direction.mag() < 1e-12 {
+                    return vec![self.position];
+                }
+                let dir1 = direction.perpendicular();
+                let dir2 = dir1.cross(direction).norm();
+                for i in 0..LIGHT_SAMPLES {
+                    for j in 0..LIGHT_SAMPLES {
+                        let offset = dir1 * ((i as f64 * step - 0.5) * 2.0 * radius) + dir2 * ((j as f64 * step - 0.5) * 2.0 * radius);
+                        if offset.mag() <= radius {
+                            points.push(self.position + offset);
+                        }
+                    }
+                }
+            }
+            LightShape::Rectangle(edge_u, edge_v) => {
+                for i in 0..LIGHT_SAMPLES {
+                    for j in 0..LIGHT_SAMPLES {
+                        let u = (i as f64 + 0.5) * step - 0.5;
+                        let v = (j as f64 + 0.5) * step - 0.5;
+                        points.push(self.position + edge_u * u + edge_v * v);
+                    }
+                }
+            }
+        }
+        points
     }
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::procedural::Gradient;
 
     #[test]
     fn test_sphere_intersect() {
         let ray = Ray::new((0.0, 0.0, 0.0), (0.0, 0.0, 1.0));
         let sphere = Sphere::new(3.0, (0.0, 0.0, 10.0), Material::new((1.0, 0.0, 0.0), 0.0));
         let hit = sphere.ray_intersects(&ray).unwrap();
-        let ray2 = Ray::new(hit, hit - Vector3::from((0.0, 0.0, 0.0)));
+        let ray2 = Ray::new(*hit.get_position(), *hit.get_position() - Vector3::from((0.0, 0.0, 0.0)));
 
         assert!(sphere.ray_intersects(&ray).is_some());
+        assert!(sphere.ray_intersects(&ray2).is_some());
+        assert_eq!(hit.get_t(), 7.0);
+        assert_eq!(*hit.get_position(), Vector3::new(0.0, 0.0, 7.0));
+        assert_eq!(*hit.get_normal(), Vector3::new(0.0, 0.0, -1.0));
+        assert!(hit.is_front_face());
+    }
+
+    #[test]
+    fn test_ray_interval() {
+        let sphere = Sphere::new(3.0, (0.0, 0.0, 10.0), Material::new((1.0, 0.0, 0.0), 0.0));
+        let ray = Ray::new((0.0, 0.0, 0.0), (0.0, 0.0, 1.0));
+
+        assert_eq!(sphere.ray_intersects(&ray.with_interval(8.0, 20.0)).unwrap().get_t(), 13.0);
+        assert!(sphere.ray_intersects(&ray.with_interval(0.0, 5.0)).is_none());
+        assert!(!sphere.occludes(&ray.with_interval(14.0, f64::INFINITY)));
+        assert!(sphere.occludes(&Ray::new((0.0, 0.0, 20000.0), (0.0, 0.0, -1.0))));
+    }
+
+    #[test]
+    fn test_cuboid_intersect() {
+        let cuboid = Cuboid::new((-1.0, -1.0, 4.0), (1.0, 1.0, 6.0), Material::new((1.0, 0.0, 0.0), 0.0));
+        let hit = cuboid.ray_intersects(&Ray::new((0.5, 0.0, 0.0), (0.0, 0.0, 1.0))).unwrap();
+        assert_eq!(hit.get_t(), 4.0);
+        assert_eq!(*hit.get_normal(), Vector3::new(0.0, 0.0, -1.0));
+        assert!((hit.get_uv().0 - 0.25).abs() < 1e-9 && (hit.get_uv().1 - 0.5).abs() < 1e-9);
+        // v runs up the face
+        assert!((hit.get_bitangent() - Vector3::new(0.0, 1.0, 0.0)).mag() < 1e-9);
+
+        let inside = cuboid.ray_intersects(&Ray::new((0.0, 0.0, 5.0), (1.0, 0.0, 0.0))).unwrap();
+        assert_eq!(inside.get_t(), 1.0);
+        assert!(!inside.is_front_face());
+
+        // Rotated 45 degrees around z, the corner now sticks out to x = sqrt(2)
+        let rotated = cuboid.rotated((0.0, 0.0, 1.0), std::f64::consts::FRAC_PI_4);
+        let hit = rotated.ray_intersects(&Ray::new((3.0, 0.0, 5.0), (-1.0, 0.0, 0.0))).unwrap();
+        assert!((hit.get_t() - (3.0 - 2f64.sqrt())).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_cylinder_intersect() {
+        let cylinder = Cylinder::new((0.0, 0.0, 10.0), (0.0, 4.0, 10.0), 1.0, Material::new((1.0, 0.0, 0.0), 0.0));
+        let side = cylinder.ray_intersects(&Ray::new((0.0, 2.0, 0.0), (0.0, 0.0, 1.0))).unwrap();
+        assert!((side.get_t() - 9.0).abs() < 1e-9);
+        assert!((*side.get_normal() - Vector3::new(0.0, 0.0, -1.0)).mag() < 1e-9);
+
+        let cap = cylinder.ray_intersects(&Ray::new((0.5, 10.0, 10.0), (0.0, -1.0, 0.0))).unwrap();
+        assert!((cap.get_t() - 6.0).abs() < 1e-9);
+        assert!((*cap.get_normal() - Vector3::new(0.0, 1.0, 0.0)).mag() < 1e-9);
+
+        assert!(cylinder.ray_intersects(&Ray::new((0.0, 5.0, 0.0), (0.0, 0.0, 1.0))).is_none());
+
+        // Down the axis from inside: the span reaches back to the top cap behind the origin
+        let spans = cylinder.intervals(&Ray::new((0.5, 2.0, 10.0), (0.0, -1.0, 0.0)));
+        assert_eq!(spans.len(), 1);
+        assert!((spans[0].0.get_t() + 2.0).abs() < 1e-9 && (spans[0].1.get_t() - 2.0).abs() < 1e-9);
+        assert!(cylinder.clone().without_caps().intervals(&Ray::new((0.0, 2.0, 0.0), (0.0, 0.0, 1.0))).is_empty());
+        assert!(cylinder.without_caps().ray_intersects(&Ray::new((0.5, 10.0, 10.0), (0.0, -1.0, 0.0))).is_none());
+    }
+
+    #[test]
+    fn test_quad_intersect() {
+        let quad = Quad::new((0.0, 0.0, 5.0), (2.0, 0.0, 0.0), (1.0, 1.0, 0.0), Material::new((1.0, 0.0, 0.0), 0.0));
+        let hit = quad.ray_intersects(&Ray::new((1.5, 0.5, 0.0), (0.0, 0.0, 1.0))).unwrap();
+        assert_eq!(hit.get_t(), 5.0);
+        assert!((hit.get_uv().0 - 0.5).abs() < 1e-9 && (hit.get_uv().1 - 0.5).abs() < 1e-9);
+        // Inside the bounding rectangle but outside the slanted edge
+        assert!(quad.ray_intersects(&Ray::new((0.2, 0.8, 0.0), (0.0, 0.0, 1.0))).is_none());
+
+        let light = Light::from_quad(&quad, (1.0, 1.0, 1.0));
+        assert_eq!(*light.get_position(), Vector3::new(1.5, 0.5, 5.0));
+        assert!((light.get_radius() - 2.5f64.sqrt()).abs() < 1e-9);
+        assert_eq!(light.sample_points(&Vector3::new(0.0, 0.0, 0.0)).len(), LIGHT_SAMPLES * LIGHT_SAMPLES);
+    }
+
+    #[test]
+    fn test_sphere_light_samples() {
+        let light = Light::new((0.0, 0.0, 10.0), (1.0, 1.0, 1.0), 2.0);
+        let samples = light.sample_points(&Vector3::new(0.0, 0.0, 0.0));
+        // The grid spans the whole disk facing the point, not just a unit square in its middle
+        assert!(samples.iter().all(|p| (*p - *light.get_position()).mag() <= 2.0 + 1e-9));
+        assert!(samples.iter().any(|p| (*p - *light.get_position()).mag() > 1.5));
+        assert_eq!(light.sample_points(light.get_position()), vec![*light.get_position()]);
+    }
+
+    #[test]
+    fn test_torus_intersect() {
+        let torus = Torus::new((0.0, 0.0, 10.0), (0.0, 1.0, 0.0), 3.0, 1.0, Material::new((1.0, 0.0, 0.0), 0.0));
+        // Straight through the tube, then through the hole without hitting anything
+        let hit = torus.ray_intersects(&Ray::new((0.0, 0.0, 0.0), (0.0, 0.0, 1.0))).unwrap();
+        assert!((hit.get_t() - 6.0).abs() < 1e-9);
+        assert!((*hit.get_normal() - Vector3::new(0.0, 0.0, -1.0)).mag() < 1e-9);
+        assert!(torus.ray_intersects(&Ray::new((0.0, 10.0, 10.0), (0.0, -1.0, 0.0))).is_none());
+        assert_eq!(torus.intervals(&Ray::new((0.0, 0.0, 0.0), (0.0, 0.0, 1.0))).len(), 2);
+        // Down onto the top of the tube
+        let top = torus.ray_intersects(&Ray::new((3.0, 10.0, 10.0), (0.0, -1.0, 0.0))).unwrap();
+        assert!((top.get_t() - 9.0).abs() < 1e-9);
+        // Grazing the outside from very far away
+        let far = torus.ray_intersects(&Ray::new((-100000.0, 0.0, 10.0), (1.0, 0.0, 0.0))).unwrap();
+        assert!((far.get_t() - 99996.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_cone_intersect() {
+        let material = Material::new((1.0, 0.0, 0.0), 0.0);
+        // 45 degree cone with its tip at y = 2
+        let cone = Cone::new((0.0, 0.0, 10.0), (0.0, 2.0, 10.0), 2.0, 0.0, material.clone());
+        let hit = cone.ray_intersects(&Ray::new((0.0, 1.0, 0.0), (0.0, 0.0, 1.0))).unwrap();
+        assert!((hit.get_t() - 9.0).abs() < 1e-9);
+        assert!((*hit.get_normal() - Vector3::new(0.0, 1.0, -1.0).norm()).mag() < 1e-9);
+        // Nothing on the mirrored cone above the tip
+        assert!(cone.ray_intersects(&Ray::new((0.0, 3.0, 0.0), (0.0, 0.0, 1.0))).is_none());
+
+        let truncated = Cone::new((0.0, 0.0, 10.0), (0.0, 1.0, 10.0), 2.0, 1.0, material.clone());
+        let top = truncated.ray_intersects(&Ray::new((0.5, 5.0, 10.0), (0.0, -1.0, 0.0))).unwrap();
+        assert!((top.get_t() - 4.0).abs() < 1e-9);
+
+        let disk = Disk::new((0.0, 0.0, 5.0), (0.0, 0.0, -1.0), 1.0, material);
+        assert!(disk.ray_intersects(&Ray::new((0.5, 0.5, 0.0), (0.0, 0.0, 1.0))).is_some());
+        assert!(disk.ray_intersects(&Ray::new((1.0, 1.0, 0.0), (0.0, 0.0, 1.0))).is_none());
+    }
+
+    #[test]
+    #[should_panic(expected = "base and top are the same point")]
+    fn test_cylinder_zero_axis() {
+        Cylinder::new((0.0, 1.0, 0.0), (0.0, 1.0, 0.0), 1.0, Material::new((1.0, 0.0, 0.0), 0.0));
+    }
+
+    #[test]
+    #[should_panic(expected = "disk normal has zero length")]
+    fn test_disk_zero_normal() {
+        Disk::new((0.0, 0.0, 0.0), (0.0, 0.0, 0.0), 1.0, Material::new((1.0, 0.0, 0.0), 0.0));
+    }
+
+    #[test]
+    fn test_instance_intersect() {
+        // A unit sphere stretched along x and moved away from the origin
+        let sphere = Arc::new(Sphere::new(1.0, (0.0, 0.0, 0.0), Material::new((1.0, 0.0, 0.0), 0.0)));
+        let transform = Transform::scale((2.0, 1.0, 1.0)).then(&Transform::translate((0.0, 0.0, 10.0)));
+        let instance = Instance::new(sphere, transform);
+
+        let front = instance.ray_intersects(&Ray::new((0.0, 0.0, 0.0), (0.0, 0.0, 1.0))).unwrap();
+        assert!((front.get_t() - 9.0).abs() < 1e-9);
+        assert!((*front.get_normal() - Vector3::new(0.0, 0.0, -1.0)).mag() < 1e-9);
+        let side = instance.ray_intersects(&Ray::new((-10.0, 0.0, 10.0), (1.0, 0.0, 0.0))).unwrap();
+        assert!((side.get_t() - 8.0).abs() < 1e-9);
+        assert!((*side.get_position() - Vector3::new(-2.0, 0.0, 10.0)).mag() < 1e-9);
+        assert!((*side.get_normal() - Vector3::new(-1.0, 0.0, 0.0)).mag() < 1e-9);
+
+        // The interval is measured in world units
+        assert!(!instance.occludes(&Ray::new((-10.0, 0.0, 10.0), (1.0, 0.0, 0.0)).with_interval(0.0, 7.9)));
+        assert!(instance.occludes(&Ray::new((-10.0, 0.0, 10.0), (1.0, 0.0, 0.0)).with_interval(0.0, 8.1)));
+    }
+
+    #[test]
+    fn test_quadric_intersect() {
+        let material = Material::new((1.0, 0.0, 0.0), 0.0);
+        // x^2 / 4 + y^2 + (z - 10)^2 = 1
+        let ellipsoid = Quadric::new([0.25, 1.0, 1.0, 0.0, 0.0, 0.0, 0.0, 0.0, -20.0, 99.0], material.clone());
+        let front = ellipsoid.ray_intersects(&Ray::new((0.0, 0.0, 0.0), (0.0, 0.0, 1.0))).unwrap();
+        assert!((front.get_t() - 9.0).abs() < 1e-9);
+        assert!((*front.get_normal() - Vector3::new(0.0, 0.0, -1.0)).mag() < 1e-9);
+        let side = ellipsoid.ray_intersects(&Ray::new((-10.0, 0.0, 10.0), (1.0, 0.0, 0.0))).unwrap();
+        assert!((side.get_t() - 8.0).abs() < 1e-9);
+
+        // Hyperboloid of one sheet x^2 + z^2 - y^2 = 1, cut off at y = +-1
+        let hyperboloid = Quadric::new([1.0, -1.0, 1.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, -1.0], material.clone())
+            .with_clip_box((-5.0, -1.0, -5.0), (5.0, 1.0, 5.0));
+        let waist = hyperboloid.ray_intersects(&Ray::new((0.0, 0.0, -5.0), (0.0, 0.0, 1.0))).unwrap();
+        assert!((waist.get_t() - 4.0).abs() < 1e-9);
+        assert!((*waist.get_normal() - Vector3::new(0.0, 0.0, -1.0)).mag() < 1e-9);
+        // The surface continues at y = +-sqrt(3), which is clipped away
+        assert!(hyperboloid.ray_intersects(&Ray::new((2.0, 5.0, 0.0), (0.0, -1.0, 0.0))).is_none());
+
+        // Paraboloid y = x^2 + z^2, opening upwards with its negative side inside
+        let paraboloid = Quadric::new([1.0, 0.0, 1.0, 0.0, 0.0, 0.0, 0.0, -1.0, 0.0, 0.0], material);
+        let bottom = paraboloid.ray_intersects(&Ray::new((0.0, 5.0, 0.0), (0.0, -1.0, 0.0))).unwrap();
+        assert!((bottom.get_t() - 5.0).abs() < 1e-9);
+        assert!((*bottom.get_normal() - Vector3::new(0.0, 1.0, 0.0)).mag() < 1e-9);
+        assert!(!bottom.is_front_face());
+    }
+
+    #[test]
+    fn test_sphere_inside() {
+        let ray = Ray::new((0.0, 0.0, 10.0), (0.0, 0.0, 1.0));
+        let sphere = Sphere::new(3.0, (0.0, 0.0, 10.0), Material::new((1.0, 0.0, 0.0), 0.0));
+        let hit = sphere.ray_intersects(&ray).unwrap();
+
+        assert_eq!(hit.get_t(), 3.0);
+        assert!(!hit.is_front_face());
+        assert_eq!(*hit.get_normal(), Vector3::new(0.0, 0.0, -1.0));
+    }
+
+    #[test]
+    fn test_pair_crossings() {
+        let material = Material::new((1.0, 0.0, 0.0), 0.0);
+        let ray = Ray::new((0.0, 0.0, 0.0), (0.0, 0.0, 1.0));
+        let crossing = |t: f64, side: f64| Hit::new(&ray, t, Vector3::new(0.0, 0.0, side), &material);
+
+        // A lone exit from a grazing crossing ahead of the real entry and exit is skipped
+        let spans = pair_crossings(vec![crossing(2.0, 1.0), crossing(0.5, 1.0), crossing(1.0, -1.0)]);
+        assert_eq!(spans.len(), 1);
+        assert_eq!((spans[0].0.get_t(), spans[0].1.get_t()), (1.0, 2.0));
+    }
+
+    #[test]
+    fn test_sphere_normal_map() {
+        // Tilted halfway towards +v, which on a sphere is north
+        let normal_map = Gradient::new((0.5, 0.75, 0.75), (0.5, 0.75, 0.75), (0.0, 0.0, 0.0), (1.0, 0.0, 0.0), 1.0);
+        let sphere = Sphere::new(3.0, (0.0, 0.0, 10.0), Material::new((1.0, 1.0, 1.0), 0.0).with_normal_map(normal_map));
+        let hit = sphere.ray_intersects(&Ray::new((10.0, 0.0, 10.0), (-1.0, 0.0, 0.0))).unwrap();
+        assert!((hit.get_bitangent() - Vector3::new(0.0, 1.0, 0.0)).mag() < 1e-9);
+
+        let shading_normal = hit.get_material().shading_normal(hit.get_shading_normal(), hit.get_tangent(), &hit.get_bitangent(), &hit.texture_coords());
+        assert!((shading_normal - Vector3::new(1.0, 1.0, 0.0).norm()).mag() < 1e-6);
     }
 
     #[test]
@@ -227,5 +1222,33 @@ mod test {
         let ray = Ray::new((0.0, 0.0, 0.0), (0.0, 0.0, 1.0));
         let plane = Plane::new((-1.0, -1.0, 2.0), (1.0, 0.0, 2.0), (0.0, 1.0, 2.0), Material::new((1.0, 0.0, 0.0), 0.0));
         let hit = plane.ray_intersects(&ray).unwrap();
+        assert_eq!(hit.get_t(), 2.0);
+    }
+
+    #[test]
+    fn test_triangle_uv() {
+        let ray = Ray::new((1.0, 0.5, 0.0), (0.0, 0.0, 1.0));
+        let triangle = Triangle::new((0.0, 0.0, 5.0), (2.0, 0.0, 5.0), (0.0, 2.0, 5.0), Material::new((1.0, 0.0, 0.0), 0.0))
+            .with_uvs((0.0, 0.0), (1.0, 0.0), (0.0, 1.0));
+        let (u, v) = triangle.ray_intersects(&ray).unwrap().get_uv();
+        assert!((u - 0.5).abs() < 1e-9 && (v - 0.25).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_triangle_smooth_normal() {
+        let triangle = Triangle::new((0.0, 0.0, 5.0), (2.0, 0.0, 5.0), (0.0, 2.0, 5.0), Material::new((1.0, 0.0, 0.0), 0.0))
+            .with_normals((0.0, 0.0, 1.0), (1.0, 0.0, 1.0), (0.0, 0.0, 1.0));
+        let near_corner = triangle.ray_intersects(&Ray::new((1.999, 0.0005, 10.0), (0.0, 0.0, -1.0))).unwrap();
+        let middle = triangle.ray_intersects(&Ray::new((1.0, 0.0005, 10.0), (0.0, 0.0, -1.0))).unwrap();
+        assert!((*near_corner.get_shading_normal() - Vector3::new(1.0, 0.0, 1.0).norm()).mag() < 1e-3);
+        assert!((*middle.get_shading_normal() - (Vector3::new(0.0, 0.0, 1.0) + Vector3::new(1.0, 0.0, 1.0).norm()).norm()).mag() < 1e-3);
+        assert!(triangle.ray_intersects(&Ray::new((1.5, 1.5, 10.0), (0.0, 0.0, -1.0))).is_none());
+    }
+
+    #[test]
+    fn test_small_triangle_intersect() {
+        let ray = Ray::new((0.001, 0.001, 0.0), (0.0, 0.0, 1.0));
+        let triangle = Triangle::new((0.0, 0.0, 5.0), (0.01, 0.0, 5.0), (0.0, 0.01, 5.0), Material::new((1.0, 0.0, 0.0), 0.0));
+        assert_eq!(triangle.ray_intersects(&ray).unwrap().get_t(), 5.0);
     }
 }
@@ -0,0 +1,155 @@
+//! Real roots of low degree polynomials, for intersecting rays with algebraic surfaces.
+//! Coefficients are given from the highest power down, and roots are returned in increasing order.
+
+const EPSILON: f64 = 1e-9;
+
+fn is_zero(x: f64) -> bool {
+    x.abs() < EPSILON
+}
+
+fn sorted(mut roots: Vec<f64>) -> Vec<f64> {
+    roots.retain(|r| r.is_finite());
+    roots.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    roots
+}
+
+/// Roots of `a x^2 + b x + c`, falling back to the linear equation when `a` is zero.
+pub fn solve_quadratic(a: f64, b: f64, c: f64) -> Vec<f64> {
+    if a == 0.0 {
+        return if b == 0.0 { Vec::new() } else { vec![-c / b] };
+    }
+    let disc = b * b - 4.0 * a * c;
+    if disc < 0.0 {
+        return Vec::new();
+    }
+    if disc == 0.0 {
+        return vec![-b / (2.0 * a)];
+    }
+    // Avoids cancellation between -b and the square root when they are close
+    let q = -0.5 * (b + b.signum() * disc.sqrt());
+    if q == 0.0 {
+        return sorted(vec![disc.sqrt() / (2.0 * a), -disc.sqrt() / (2.0 * a)]);
+    }
+    sorted(vec![q / a, c / q])
+}
+
+/// Roots of `a x^3 + b x^2 + c x + d`, using Cardano's method.
+pub fn solve_cubic(a: f64, b: f64, c: f64, d: f64) -> Vec<f64> {
+    if a == 0.0 {
+        return solve_quadratic(b, c, d);
+    }
+    // Normal form x^3 + A x^2 + B x + C, then substitute x = y - A/3 to remove the square term
+    let (a2, b2, c2) = (b / a, c / a, d / a);
+    let sq_a = a2 * a2;
+    let p = (-sq_a / 3.0 + b2) / 3.0;
+    let q = (2.0 / 27.0 * a2 * sq_a - a2 * b2 / 3.0 + c2) / 2.0;
+    let cb_p = p * p * p;
+    let disc = q * q + cb_p;
+
+    let roots = if is_zero(disc) {
+        if is_zero(q) {
+            vec![0.0]
+        } else {
+            let u = (-q).cbrt();
+            vec![2.0 * u, -u]
+        }
+    } else if disc < 0.0 {
+        // Three real roots
+        let phi = (-q / (-cb_p).sqrt()).clamp(-1.0, 1.0).acos() / 3.0;
+        let t = 2.0 * (-p).sqrt();
+        vec![
+            t * phi.cos(),
+            -t * (phi + std::f64::consts::PI / 3.0).cos(),
+            -t * (phi - std::f64::consts::PI / 3.0).cos(),
+        ]
+    } else {
+        let sqrt_disc = disc.sqrt();
+        vec![(sqrt_disc - q).cbrt() - (sqrt_disc + q).cbrt()]
+    };
+
+    sorted(roots.into_iter().map(|r| r - a2 / 3.0).collect())
+}
+
+/// Roots of `a x^4 + b x^3 + c x^2 + d x + e`, using Ferrari's method with the roots polished by
+/// Newton's method on the original polynomial.
+pub fn solve_quartic(a: f64, b: f64, c: f64, d: f64, e: f64) -> Vec<f64> {
+    if a == 0.0 {
+        return solve_cubic(b, c, d, e);
+    }
+    // Normal form x^4 + A x^3 + B x^2 + C x + D, then substitute x = y - A/4 to remove the cube term
+    let (a2, b2, c2, d2) = (b / a, c / a, d / a, e / a);
+    let sq_a = a2 * a2;
+    let p = -3.0 / 8.0 * sq_a + b2;
+    let q = sq_a * a2 / 8.0 - a2 * b2 / 2.0 + c2;
+    let r = -3.0 / 256.0 * sq_a * sq_a + sq_a * b2 / 16.0 - a2 * c2 / 4.0 + d2;
+
+    let mut roots = if is_zero(r) {
+        // y (y^3 + p y + q) = 0
+        let mut roots = solve_cubic(1.0, 0.0, p, q);
+        roots.push(0.0);
+        roots
+    } else {
+        // Split into two quadratics using one root of the resolvent cubic
+        let z = match solve_cubic(1.0, -p / 2.0, -r, r * p / 2.0 - q * q / 8.0).last() {
+            Some(&z) => z,
+            None => return Vec::new(),
+        };
+        let u = z * z - r;
+        let v = 2.0 * z - p;
+        let u = if is_zero(u) { 0.0 } else if u > 0.0 { u.sqrt() } else { return Vec::new() };
+        let v = if is_zero(v) { 0.0 } else if v > 0.0 { v.sqrt() } else { return Vec::new() };
+        let v = if q < 0.0 { -v } else { v };
+
+        let mut roots = solve_quadratic(1.0, v, z - u);
+        roots.extend(solve_quadratic(1.0, -v, z + u));
+        roots
+    };
+
+    for root in roots.iter_mut() {
+        *root -= a2 / 4.0;
+        for _ in 0..2 {
+            let x = *root;
+            let value = (((a * x + b) * x + c) * x + d) * x + e;
+            let slope = ((4.0 * a * x + 3.0 * b) * x + 2.0 * c) * x + d;
+            if slope != 0.0 {
+                *root = x - value / slope;
+            }
+        }
+    }
+    sorted(roots)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn assert_roots(found: Vec<f64>, expected: &[f64]) {
+        assert_eq!(found.len(), expected.len(), "found roots {:?}", found);
+        for (f, e) in found.iter().zip(expected) {
+            assert!((f - e).abs() < 1e-6, "found roots {:?}", found);
+        }
+    }
+
+    #[test]
+    fn test_quadratic() {
+        assert_roots(solve_quadratic(1.0, -3.0, 2.0), &[1.0, 2.0]);
+        assert_roots(solve_quadratic(1.0, 0.0, 1.0), &[]);
+        assert_roots(solve_quadratic(0.0, 2.0, -4.0), &[2.0]);
+    }
+
+    #[test]
+    fn test_cubic() {
+        // (x - 1)(x - 2)(x + 3)
+        assert_roots(solve_cubic(1.0, 0.0, -7.0, 6.0), &[-3.0, 1.0, 2.0]);
+        assert_roots(solve_cubic(2.0, 0.0, 0.0, -16.0), &[2.0]);
+    }
+
+    #[test]
+    fn test_quartic() {
+        // (x - 1)(x - 2)(x - 3)(x - 4)
+        assert_roots(solve_quartic(1.0, -10.0, 35.0, -50.0, 24.0), &[1.0, 2.0, 3.0, 4.0]);
+        // (x^2 + 1)(x - 5)(x + 0.5)
+        assert_roots(solve_quartic(1.0, -4.5, -1.5, -4.5, -2.5), &[-0.5, 5.0]);
+        assert_roots(solve_quartic(1.0, 0.0, 2.0, 0.0, 1.0), &[]);
+    }
+}
@@ -0,0 +1,287 @@
+use crate::basics::{Vector3, Color};
+use std::fs::File;
+use std::io::{BufReader, Read, Error, ErrorKind};
+use std::path::Path;
+
+/// Where on a surface a texture is being sampled.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct TextureCoords {
+    uv: (f64, f64),
+    position: Vector3,
+    object_position: Vector3,
+}
+
+impl TextureCoords {
+    pub fn new<T: Into<Vector3>, U: Into<Vector3>>(uv: (f64, f64), position: T, object_position: U) -> Self {
+        Self { uv, position: position.into(), object_position: object_position.into() }
+    }
+
+    pub fn get_uv(&self) -> (f64, f64) {
+        self.uv
+    }
+
+    pub fn get_position(&self) -> &Vector3 {
+        &self.position
+    }
+
+    /// The hit position relative to the object's own origin, so textures move along with the object.
+    pub fn get_object_position(&self) -> &Vector3 {
+        &self.object_position
+    }
+
+    /// The same coordinates moved a small step along the surface, for taking finite differences.
+    pub fn shifted(&self, duv: (f64, f64), offset: Vector3) -> Self {
+        Self {
+            uv: (self.uv.0 + duv.0, self.uv.1 + duv.1),
+            position: self.position + offset,
+            object_position: self.object_position + offset,
+        }
+    }
+}
+
+pub trait Texture {
+    fn color_at(&self, coords: &TextureCoords) -> Color;
+}
+
+/// How texture coordinates outside of [0, 1] are mapped back onto the image.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum WrapMode {
+    Repeat,
+    MirroredRepeat,
+    Clamp,
+}
+
+impl WrapMode {
+    fn wrap(&self, index: isize, size: usize) -> usize {
+        let size = size as isize;
+        match self {
+            WrapMode::Repeat => index.rem_euclid(size) as usize,
+            WrapMode::MirroredRepeat => {
+                let period = index.rem_euclid(2 * size);
+                if period < size {
+                    period as usize
+                } else {
+                    (2 * size - 1 - period) as usize
+                }
+            }
+            WrapMode::Clamp => index.clamp(0, size - 1) as usize,
+        }
+    }
+}
+
+/// An image sampled with bilinear filtering. UV (0, 0) is the bottom left corner of the image.
+#[derive(Clone, PartialEq, Debug)]
+pub struct ImageTexture {
+    width: usize,
+    height: usize,
+    pixels: Vec<Color>,
+    wrap: WrapMode,
+    scale: (f64, f64),
+}
+
+impl ImageTexture {
+    pub fn new(width: usize, height: usize, pixels: Vec<Color>) -> Self {
+        assert_eq!(pixels.len(), width * height, "pixel count doesn't match image size");
+        assert!(width > 0 && height > 0, "image is empty");
+        Self { width, height, pixels, wrap: WrapMode::Repeat, scale: (1.0, 1.0) }
+    }
+
+    /// Loads a PNG or PPM (P3 or P6) image, picked by file extension.
+    pub fn load<P: AsRef<Path>>(path: P) -> std::io::Result<Self> {
+        let path = path.as_ref();
+        let extension = path.extension().and_then(|e| e.to_str()).map(|e| e.to_ascii_lowercase());
+        match extension.as_deref() {
+            Some("png") => Self::load_png(path),
+            Some("ppm") => Self::load_ppm(path),
+            _ => Err(Error::new(ErrorKind::InvalidInput, format!("unsupported image format: {}", path.display()))),
+        }
+    }
+
+    fn load_png(path: &Path) -> std::io::Result<Self> {
+        let mut decoder = png::Decoder::new(File::open(path)?);
+        decoder.set_transformations(png::Transformations::normalize_to_color8());
+        let mut reader = decoder.read_info().map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
+        let mut buf = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut buf).map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
+
+        let channels = info.color_type.samples();
+        let (width, height) = (info.width as usize, info.height as usize);
+        let pixels = buf[..info.buffer_size()].chunks(channels).map(|p| {
+            let c = |v: u8| v as f64 / 255.0;
+            match channels {
+                1 | 2 => Color::new(c(p[0]), c(p[0]), c(p[0])),
+                _ => Color::new(c(p[0]), c(p[1]), c(p[2])),
+            }
+        }).collect();
+
+        Ok(Self::new(width, height, pixels))
+    }
+
+    fn load_ppm(path: &Path) -> std::io::Result<Self> {
+        let mut data = Vec::new();
+        BufReader::new(File::open(path)?).read_to_end(&mut data)?;
+        Self::parse_ppm(&data)
+    }
+
+    fn parse_ppm(data: &[u8]) -> std::io::Result<Self> {
+        let invalid = |msg: &str| Error::new(ErrorKind::InvalidData, format!("bad ppm: {}", msg));
+
+        // Reads the next whitespace separated header token, skipping comments
+        let mut pos = 0;
+        let mut next_token = || -> Option<String> {
+            loop {
+                while pos < data.len() && data[pos].is_ascii_whitespace() {
+                    pos += 1;
+                }
+                if pos < data.len() && data[pos] == b'#' {
+                    while pos < data.len() && data[pos] != b'\n' {
+                        pos += 1;
+                    }
+                } else {
+                    break;
+                }
+            }
+            let start = pos;
+            while pos < data.len() && !data[pos].is_ascii_whitespace() {
+                pos += 1;
+            }
+            if start == pos {
+                None
+            } else {
+                Some(String::from_utf8_lossy(&data[start..pos]).into_owned())
+            }
+        };
+
+        let magic = next_token().unwrap_or_default();
+        if magic != "P3" && magic != "P6" {
+            return Err(invalid("unknown magic number"));
+        }
+
+        let mut next_number = |what: &str| -> std::io::Result<usize> {
+            next_token().and_then(|t| t.parse().ok()).ok_or_else(|| invalid(what))
+        };
+        let width = next_number("width")?;
+        let height = next_number("height")?;
+        let max_value = next_number("max value")?;
+        if width == 0 || height == 0 || max_value == 0 || max_value > 255 {
+            return Err(invalid("unsupported header"));
+        }
+
+        let sample_count = width.checked_mul(height).and_then(|n| n.checked_mul(3)).ok_or_else(|| invalid("image too large"))?;
+        let samples: Vec<usize> = if magic == "P3" {
+            (0..sample_count).map(|_| next_number("pixel data")).collect::<std::io::Result<_>>()?
+        } else {
+            // Exactly one whitespace byte separates the header from the binary data
+            let start = pos + 1;
+            let end = start.checked_add(sample_count).ok_or_else(|| invalid("image too large"))?;
+            let bytes = data.get(start..end).ok_or_else(|| invalid("truncated pixel data"))?;
+            bytes.iter().map(|&b| b as usize).collect()
+        };
+
+        let pixels = samples.chunks(3).map(|p| {
+            let c = |v: usize| v as f64 / max_value as f64;
+            Color::new(c(p[0]), c(p[1]), c(p[2]))
+        }).collect();
+
+        Ok(Self::new(width, height, pixels))
+    }
+
+    pub fn with_wrap(mut self, wrap: WrapMode) -> Self {
+        self.wrap = wrap;
+        self
+    }
+
+    /// Number of times the image repeats per unit of UV in each direction.
+    pub fn with_scale(mut self, u_scale: f64, v_scale: f64) -> Self {
+        self.scale = (u_scale, v_scale);
+        self
+    }
+
+    /// Width and height in pixels.
+    pub fn get_size(&self) -> (usize, usize) {
+        (self.width, self.height)
+    }
+
+    /// The pixel in column `x` and row `y`, counting rows from the top.
+    pub fn get_pixel(&self, x: usize, y: usize) -> Color {
+        self.pixels[y * self.width + x]
+    }
+
+    fn texel(&self, x: isize, y: isize) -> Vector3 {
+        let x = self.wrap.wrap(x, self.width);
+        let y = self.wrap.wrap(y, self.height);
+        Vector3::from(self.pixels[y * self.width + x])
+    }
+
+    pub fn sample(&self, u: f64, v: f64) -> Color {
+        // Texel centers sit at half-integer positions, image rows are stored top to bottom
+        let x = u * self.scale.0 * self.width as f64 - 0.5;
+        let y = (1.0 - v * self.scale.1) * self.height as f64 - 0.5;
+        let (x0, y0) = (x.floor(), y.floor());
+        let (fx, fy) = (x - x0, y - y0);
+        let (x0, y0) = (x0 as isize, y0 as isize);
+
+        let top = self.texel(x0, y0) * (1.0 - fx) + self.texel(x0 + 1, y0) * fx;
+        let bottom = self.texel(x0, y0 + 1) * (1.0 - fx) + self.texel(x0 + 1, y0 + 1) * fx;
+        (top * (1.0 - fy) + bottom * fy).into()
+    }
+}
+
+impl Texture for ImageTexture {
+    fn color_at(&self, coords: &TextureCoords) -> Color {
+        self.sample(coords.uv.0, coords.uv.1)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn checker() -> ImageTexture {
+        let black = Color::new(0.0, 0.0, 0.0);
+        let white = Color::new(1.0, 1.0, 1.0);
+        ImageTexture::new(2, 2, vec![black, white, white, black])
+    }
+
+    #[test]
+    fn test_bilinear_sample() {
+        let texture = checker().with_wrap(WrapMode::Clamp);
+        assert_eq!(texture.sample(0.25, 0.75), Color::new(0.0, 0.0, 0.0));
+        assert_eq!(texture.sample(0.75, 0.75), Color::new(1.0, 1.0, 1.0));
+        assert_eq!(texture.sample(0.5, 0.5), Color::new(0.5, 0.5, 0.5));
+    }
+
+    #[test]
+    fn test_wrap_modes() {
+        assert_eq!(WrapMode::Repeat.wrap(-1, 4), 3);
+        assert_eq!(WrapMode::MirroredRepeat.wrap(-1, 4), 0);
+        assert_eq!(WrapMode::MirroredRepeat.wrap(5, 4), 2);
+        assert_eq!(WrapMode::Clamp.wrap(7, 4), 3);
+    }
+
+    #[test]
+    fn test_parse_ppm() {
+        let ascii = ImageTexture::parse_ppm(b"P3\n# comment\n2 1 255\n255 0 0 0 0 255\n").unwrap();
+        let binary = ImageTexture::parse_ppm(b"P6 2 1 255\n\xff\x00\x00\x00\x00\xff").unwrap();
+        assert_eq!(ascii, binary);
+        assert_eq!(ascii.pixels[1], Color::new(0.0, 0.0, 1.0));
+        assert!(ImageTexture::parse_ppm(b"P6 18446744073709551615 2 255\n").is_err());
+        assert!(ImageTexture::parse_ppm(b"P6 6148914691236517205 1 255\n").is_err());
+    }
+
+    #[test]
+    fn test_load_png() {
+        let path = std::env::temp_dir().join(format!("raycaster_test_{}.png", std::process::id()));
+        {
+            let mut encoder = png::Encoder::new(File::create(&path).unwrap(), 2, 1);
+            encoder.set_color(png::ColorType::Rgb);
+            encoder.set_depth(png::BitDepth::Eight);
+            encoder.write_header().unwrap().write_image_data(&[255, 0, 0, 0, 0, 255]).unwrap();
+        }
+        let texture = ImageTexture::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(texture.get_size(), (2, 1));
+        assert_eq!(texture.pixels, vec![Color::new(1.0, 0.0, 0.0), Color::new(0.0, 0.0, 1.0)]);
+    }
+}